@@ -0,0 +1,113 @@
+use dash_spv_primitives::hashes::{sha256d, Hash};
+
+const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Base58Error {
+    // Character outside of the base58 alphabet
+    InvalidCharacter(char),
+    // Less than the 4 checksum bytes
+    TooShort,
+    // The checksum doesn't match the data
+    BadChecksum,
+}
+
+pub fn encode(data: &[u8]) -> String {
+    let zeros = data.iter().take_while(|b| **b == 0).count();
+    // little-endian base58 digits
+    let mut digits: Vec<u8> = Vec::with_capacity(data.len() * 138 / 100 + 1);
+    for byte in &data[zeros..] {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    std::iter::repeat_n('1', zeros)
+        .chain(digits.iter().rev().map(|d| ALPHABET[*d as usize] as char))
+        .collect()
+}
+
+pub fn decode(string: &str) -> Result<Vec<u8>, Base58Error> {
+    let zeros = string.chars().take_while(|c| *c == '1').count();
+    // little-endian bytes
+    let mut bytes: Vec<u8> = Vec::with_capacity(string.len());
+    for c in string.chars().skip(zeros) {
+        let mut carry = ALPHABET
+            .iter()
+            .position(|a| *a as char == c)
+            .ok_or(Base58Error::InvalidCharacter(c))? as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    Ok(std::iter::repeat_n(0u8, zeros)
+        .chain(bytes.into_iter().rev())
+        .collect())
+}
+
+/// Base58 of the data followed by the first 4 bytes of its double SHA256
+pub fn encode_check(data: &[u8]) -> String {
+    let checksum = sha256d::Hash::hash(data).into_inner();
+    let mut buffer = data.to_vec();
+    buffer.extend_from_slice(&checksum[..4]);
+    encode(&buffer)
+}
+
+pub fn decode_check(string: &str) -> Result<Vec<u8>, Base58Error> {
+    let mut data = decode(string)?;
+    if data.len() < 4 {
+        return Err(Base58Error::TooShort);
+    }
+    let checksum = data.split_off(data.len() - 4);
+    if sha256d::Hash::hash(&data).into_inner()[..4] != checksum[..] {
+        return Err(Base58Error::BadChecksum);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::base58::{decode, decode_check, encode, encode_check, Base58Error};
+    use dash_spv_primitives::hashes::hex::FromHex;
+
+    #[test]
+    fn base58_vectors() {
+        let vectors = [
+            ("", ""),
+            ("61", "2g"),
+            ("626262", "a3gV"),
+            ("73696d706c792061206c6f6e6720737472696e67", "2cFupjhnEsSn59qHXstmK2ffpLv2"),
+            (
+                "00eb15231dfceb60925886b67d065299925915aeb172c06647",
+                "1NS17iag9jJgTHD1VXjvLCEnZuQ3rJDE9L",
+            ),
+            ("0000287fb4cd", "11233QC4"),
+        ];
+        for (hex, string) in vectors {
+            let data = Vec::<u8>::from_hex(hex).unwrap();
+            assert_eq!(encode(&data), string);
+            assert_eq!(decode(string), Ok(data));
+        }
+        // P2PKH address paid by the bitcoin genesis block
+        let payload = Vec::<u8>::from_hex("0062e907b15cbf27d5425399ebf6f0fb50ebb88f18").unwrap();
+        assert_eq!(encode_check(&payload), "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa");
+        assert_eq!(decode_check("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"), Ok(payload));
+        assert_eq!(
+            decode_check("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNb"),
+            Err(Base58Error::BadChecksum)
+        );
+        assert_eq!(decode("1l"), Err(Base58Error::InvalidCharacter('l')));
+    }
+}
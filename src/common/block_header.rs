@@ -0,0 +1,223 @@
+use crate::common::{Block, ChainType};
+use crate::error::{read_field, Error};
+use byte::ctx::Endian;
+use byte::TryRead;
+use dash_spv_primitives::consensus::Encodable;
+use dash_spv_primitives::crypto::UInt256;
+use std::cmp::Ordering;
+
+pub const BLOCK_HEADER_SIZE: usize = 80;
+
+/// Compares hashes and targets as 256-bit little-endian numbers
+pub fn compare_as_number(a: &UInt256, b: &UInt256) -> Ordering {
+    a.0.iter().rev().cmp(b.0.iter().rev())
+}
+
+/// Target encoded as nBits, as arith_uint256::SetCompact.
+/// None for negative, overflowing or zero targets, which no hash can meet
+pub fn target_from_compact(bits: u32) -> Option<UInt256> {
+    let size = (bits >> 24) as usize;
+    let word = bits & 0x007f_ffff;
+    if word == 0 || bits & 0x0080_0000 != 0 {
+        return None;
+    }
+    if size > 34 || (word > 0xff && size > 33) || (word > 0xffff && size > 32) {
+        return None;
+    }
+    let mut target = [0u8; 32];
+    if size <= 3 {
+        let word = word >> (8 * (3 - size));
+        target[..4].copy_from_slice(&word.to_le_bytes());
+    } else {
+        for (i, byte) in word.to_le_bytes()[..3].iter().enumerate() {
+            if let Some(position) = target.get_mut(size - 3 + i) {
+                *position = *byte;
+            }
+        }
+    }
+    if target == [0u8; 32] {
+        None
+    } else {
+        Some(UInt256(target))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BlockHeader {
+    pub version: u32,
+    pub prev_block_hash: UInt256,
+    pub merkle_root: UInt256,
+    pub timestamp: u32,
+    // Compact encoding of the proof-of-work target
+    pub bits: u32,
+    pub nonce: u32,
+}
+
+impl<'a> TryRead<'a, Endian> for BlockHeader {
+    fn try_read(bytes: &'a [u8], _endian: Endian) -> byte::Result<(Self, usize)> {
+        let offset = &mut 0;
+        let header = Self::decode(bytes, offset)?;
+        Ok((header, *offset))
+    }
+}
+
+impl BlockHeader {
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        let version = read_field::<u32>(bytes, offset, "BlockHeader", "version")?;
+        let prev_block_hash =
+            read_field::<UInt256>(bytes, offset, "BlockHeader", "prev_block_hash")?;
+        let merkle_root = read_field::<UInt256>(bytes, offset, "BlockHeader", "merkle_root")?;
+        let timestamp = read_field::<u32>(bytes, offset, "BlockHeader", "timestamp")?;
+        let bits = read_field::<u32>(bytes, offset, "BlockHeader", "bits")?;
+        let nonce = read_field::<u32>(bytes, offset, "BlockHeader", "nonce")?;
+        Ok(BlockHeader {
+            version,
+            prev_block_hash,
+            merkle_root,
+            timestamp,
+            bits,
+            nonce,
+        })
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::with_capacity(BLOCK_HEADER_SIZE);
+        let offset: &mut usize = &mut 0;
+        *offset += self.version.consensus_encode(&mut buffer).unwrap();
+        *offset += self.prev_block_hash.consensus_encode(&mut buffer).unwrap();
+        *offset += self.merkle_root.consensus_encode(&mut buffer).unwrap();
+        *offset += self.timestamp.consensus_encode(&mut buffer).unwrap();
+        *offset += self.bits.consensus_encode(&mut buffer).unwrap();
+        *offset += self.nonce.consensus_encode(&mut buffer).unwrap();
+        buffer
+    }
+
+    /// X11 hash of the serialized header, in little-endian byte order like all hashes here
+    pub fn block_hash(&self) -> UInt256 {
+        UInt256(rs_x11_hash::get_x11_hash(self.to_data()))
+    }
+
+    pub fn target(&self) -> Option<UInt256> {
+        target_from_compact(self.bits)
+    }
+
+    /// CheckProofOfWork: the target is valid and within the chain powLimit,
+    /// and the block hash doesn't exceed it
+    pub fn has_valid_proof_of_work(&self, chain_type: ChainType) -> bool {
+        self.meets_target(&self.block_hash(), chain_type)
+    }
+
+    /// Proof-of-work check for an already computed block hash
+    pub fn meets_target(&self, block_hash: &UInt256, chain_type: ChainType) -> bool {
+        match self.target() {
+            Some(target) => {
+                compare_as_number(&target, &chain_type.pow_limit()) != Ordering::Greater
+                    && compare_as_number(block_hash, &target) != Ordering::Greater
+            }
+            None => false,
+        }
+    }
+
+    pub fn to_block(&self, height: u32) -> Block {
+        Block {
+            height,
+            hash: self.block_hash(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::block_header::{target_from_compact, BlockHeader};
+    use crate::common::chain_type::{DevnetType, IHaveChainSettings};
+    use crate::common::ChainType;
+    use crate::tx::Transaction;
+    use dash_spv_primitives::crypto::byte_util::Reversable;
+    use dash_spv_primitives::crypto::UInt256;
+    use dash_spv_primitives::hashes::hex::FromHex;
+
+    fn genesis() -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            prev_block_hash: UInt256([0u8; 32]),
+            merkle_root: UInt256::from_hex(
+                "e0028eb9648db56b1ac77cf090b99048a8007e2bb64b68f092c03c7f56a662c7",
+            )
+            .unwrap()
+            .reversed(),
+            timestamp: 1390095618,
+            bits: 0x1e0ffff0,
+            nonce: 28917698,
+        }
+    }
+
+    #[test]
+    fn compact_targets() {
+        let mut target = [0u8; 32];
+        target[27..30].copy_from_slice(&[0xf0, 0xff, 0x0f]);
+        assert_eq!(target_from_compact(0x1e0ffff0), Some(UInt256(target)));
+        let mut target = [0u8; 32];
+        target[0] = 0x12;
+        assert_eq!(target_from_compact(0x01123456), Some(UInt256(target)));
+        target[0] = 0x56;
+        target[1] = 0x34;
+        target[2] = 0x12;
+        assert_eq!(target_from_compact(0x03123456), Some(UInt256(target)));
+        // zero, negative and overflowing targets
+        assert_eq!(target_from_compact(0x00123456), None);
+        assert_eq!(target_from_compact(0x04923456), None);
+        assert_eq!(target_from_compact(0xff123456), None);
+        assert_eq!(target_from_compact(0x21010000), None);
+        assert!(target_from_compact(0x20010000).is_some());
+    }
+
+    #[test]
+    fn header_round_trip_and_proof_of_work() {
+        let header = genesis();
+        let data = header.to_data();
+        assert_eq!(data.len(), 80);
+        let offset = &mut 0;
+        assert_eq!(BlockHeader::decode(&data, offset), Ok(header));
+        assert_eq!(*offset, 80);
+        assert!(BlockHeader::decode(&data[..79], &mut 0).is_err());
+
+        let mainnet = ChainType::MainNet;
+        let genesis_hash = UInt256::from_hex(
+            "00000ffd590b1485b3caadc19b22e6379c733355108f107a430458cdf3407ab6",
+        )
+        .unwrap()
+        .reversed();
+        assert!(header.meets_target(&genesis_hash, mainnet));
+        assert!(!header.meets_target(&UInt256([0xff; 32]), mainnet));
+        // above the powLimit
+        let mut easy = header;
+        easy.bits = 0x207fffff;
+        assert!(!easy.meets_target(&genesis_hash, mainnet));
+        assert!(easy.meets_target(&genesis_hash, ChainType::DevNet(DevnetType::Devnet333)));
+    }
+
+    #[test]
+    fn genesis_header_commits_to_genesis_coinbase() {
+        // the only transaction of the genesis block, as built by Dash Core's chainparams
+        let coinbase = Vec::<u8>::from_hex(concat!(
+            "01000000010000000000000000000000000000000000000000000000000000000000000000ffffff",
+            "ff6204ffff001d01044c5957697265642030392f4a616e2f3230313420546865204772616e642045",
+            "78706572696d656e7420476f6573204c6976653a204f76657273746f636b2e636f6d204973204e6f",
+            "7720416363657074696e6720426974636f696e73ffffffff0100f2052a010000004341040184710f",
+            "a689ad5023690c80f3a49c8f13f8d45b8c857fbcbc8bc4a8e4d3eb4b10f4d4604fa08dce601aaf0f",
+            "470216fe1b51850b4acf21b179c45070ac7b03a9ac00000000",
+        ))
+        .unwrap();
+        let tx = Transaction::decode(&coinbase, &mut 0).expect("tx should be parsed");
+        assert_eq!(tx.tx_hash, Some(genesis().merkle_root));
+        assert_eq!(tx.outputs[0].amount, 50 * 100_000_000);
+    }
+
+    #[test]
+    fn genesis_block_hash_is_x11() {
+        let header = genesis();
+        let block = header.to_block(0);
+        assert_eq!(block.hash, ChainType::MainNet.genesis_hash());
+        assert!(header.has_valid_proof_of_work(ChainType::MainNet));
+    }
+}
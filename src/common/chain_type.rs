@@ -42,6 +42,37 @@ impl ChainType {
         *self == ChainType::MainNet
     }
 
+    /// Version byte of P2PKH addresses: 'X' on mainnet, 'y' elsewhere
+    pub fn pubkey_address_prefix(&self) -> u8 {
+        match self {
+            ChainType::MainNet => 76,
+            ChainType::TestNet | ChainType::DevNet(_) => 140,
+        }
+    }
+
+    /// Highest proof-of-work target (powLimit) in little-endian byte order
+    pub fn pow_limit(&self) -> UInt256 {
+        let mut limit = [0xffu8; 32];
+        match self {
+            ChainType::MainNet | ChainType::TestNet => {
+                // 00000fffff...
+                limit[29] = 0x0f;
+                limit[30] = 0;
+                limit[31] = 0;
+            }
+            ChainType::DevNet(_) => limit[31] = 0x7f,
+        }
+        UInt256(limit)
+    }
+
+    /// Version byte of P2SH addresses: '7' on mainnet, '8' elsewhere
+    pub fn script_address_prefix(&self) -> u8 {
+        match self {
+            ChainType::MainNet => 16,
+            ChainType::TestNet | ChainType::DevNet(_) => 19,
+        }
+    }
+
 }
 
 impl IHaveChainSettings for ChainType {
@@ -0,0 +1,268 @@
+use crate::common::{Block, BlockHeader, ChainType};
+use dash_spv_primitives::crypto::UInt256;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HeaderChainError {
+    // The previous block of the header is not stored
+    UnknownParent,
+    // The header is already stored
+    DuplicateHeader,
+    // The header hash doesn't meet its target or the target exceeds the chain powLimit
+    InvalidProofOfWork,
+    // The parent is stored at the highest representable height
+    HeightOverflow,
+}
+
+/// Best tip update caused by a new header
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TipChange {
+    pub tip: Block,
+    // Blocks that left the best chain on a reorg, from the old tip down
+    pub disconnected: Vec<Block>,
+}
+
+#[derive(Clone, Debug)]
+struct StoredHeader {
+    header: BlockHeader,
+    height: u32,
+    // Approximate cumulative work, see header_work
+    chain_work: u128,
+}
+
+/// Work of a header, 2^256 / (target + 1), computed from the top 128 bits of the target.
+/// Dash targets keep it far below u128::MAX; smaller targets saturate
+pub fn header_work(header: &BlockHeader) -> u128 {
+    let target = match header.target() {
+        Some(target) => target,
+        None => return 0,
+    };
+    let high = u128::from_le_bytes(target.0[16..].try_into().unwrap());
+    match high.checked_add(1) {
+        Some(divisor) => u128::MAX / divisor,
+        None => 1,
+    }
+}
+
+/// Headers connected from a base header (genesis or checkpoint) with their heights,
+/// following the chain with the most work.
+/// Difficulty transitions (DarkGravityWave) aren't validated: a header only has to meet its own
+/// bits, capped at the chain powLimit, so the best chain should be anchored by checkpoints
+#[derive(Clone, Debug)]
+pub struct HeaderChain {
+    pub chain_type: ChainType,
+    base_height: u32,
+    headers: HashMap<UInt256, StoredHeader>,
+    // Block hashes of the best chain from the base header on
+    best_chain: Vec<UInt256>,
+}
+
+impl HeaderChain {
+    /// The base header is trusted as is: its proof of work isn't checked
+    pub fn new(chain_type: ChainType, base: BlockHeader, base_height: u32) -> Self {
+        let hash = base.block_hash();
+        let stored = StoredHeader {
+            header: base,
+            height: base_height,
+            chain_work: header_work(&base),
+        };
+        Self {
+            chain_type,
+            base_height,
+            headers: HashMap::from([(hash, stored)]),
+            best_chain: vec![hash],
+        }
+    }
+
+    /// Connects a header to a stored one. Returns the tip change when the header
+    /// extends the best chain or makes another chain the one with the most work.
+    /// Its bits aren't checked against the ones expected from the previous headers
+    pub fn add_header(
+        &mut self,
+        header: BlockHeader,
+    ) -> Result<Option<TipChange>, HeaderChainError> {
+        let hash = header.block_hash();
+        if self.headers.contains_key(&hash) {
+            return Err(HeaderChainError::DuplicateHeader);
+        }
+        let parent = self
+            .headers
+            .get(&header.prev_block_hash)
+            .ok_or(HeaderChainError::UnknownParent)?;
+        if !header.meets_target(&hash, self.chain_type) {
+            return Err(HeaderChainError::InvalidProofOfWork);
+        }
+        let height = parent
+            .height
+            .checked_add(1)
+            .ok_or(HeaderChainError::HeightOverflow)?;
+        let stored = StoredHeader {
+            header,
+            height,
+            chain_work: parent.chain_work.saturating_add(header_work(&header)),
+        };
+        let is_new_best = stored.chain_work > self.tip_header().chain_work;
+        self.headers.insert(hash, stored);
+        if !is_new_best {
+            return Ok(None);
+        }
+        Ok(Some(self.switch_tip(hash)))
+    }
+
+    fn switch_tip(&mut self, hash: UInt256) -> TipChange {
+        // walk back from the new tip until the best chain is met
+        let mut connected = vec![];
+        let mut current = hash;
+        while !self.is_in_best_chain(current) {
+            connected.push(current);
+            current = self.headers[&current].header.prev_block_hash;
+        }
+        let fork_index = (self.headers[&current].height - self.base_height) as usize;
+        let disconnected = self
+            .best_chain
+            .drain(fork_index + 1..)
+            .rev()
+            .map(|hash| Block {
+                height: self.headers[&hash].height,
+                hash,
+            })
+            .collect();
+        self.best_chain.extend(connected.into_iter().rev());
+        TipChange {
+            tip: self.tip(),
+            disconnected,
+        }
+    }
+
+    fn tip_header(&self) -> &StoredHeader {
+        &self.headers[self.best_chain.last().unwrap()]
+    }
+
+    pub fn tip(&self) -> Block {
+        let hash = *self.best_chain.last().unwrap();
+        Block {
+            height: self.headers[&hash].height,
+            hash,
+        }
+    }
+
+    pub fn header(&self, hash: &UInt256) -> Option<&BlockHeader> {
+        self.headers.get(hash).map(|stored| &stored.header)
+    }
+
+    /// Block hash at height in the best chain
+    pub fn hash_at(&self, height: u32) -> Option<UInt256> {
+        let index = height.checked_sub(self.base_height)?;
+        self.best_chain.get(index as usize).copied()
+    }
+
+    pub fn is_in_best_chain(&self, hash: UInt256) -> bool {
+        self.headers
+            .get(&hash)
+            .is_some_and(|stored| self.hash_at(stored.height) == Some(hash))
+    }
+
+    /// Height of any stored header, u32::MAX for unknown blocks
+    /// as expected by MNListDiff::new and MasternodeList
+    pub fn height_for(&self, hash: UInt256) -> u32 {
+        self.headers
+            .get(&hash)
+            .map_or(u32::MAX, |stored| stored.height)
+    }
+
+    /// Lookup to pass as block_height_lookup when parsing diffs
+    pub fn block_height_lookup(&self) -> impl Fn(UInt256) -> u32 + '_ {
+        move |hash| self.height_for(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::chain_type::DevnetType;
+    use crate::common::header_chain::{HeaderChain, HeaderChainError};
+    use crate::common::{Block, BlockHeader, ChainType};
+    use dash_spv_primitives::crypto::UInt256;
+
+    const CHAIN_TYPE: ChainType = ChainType::DevNet(DevnetType::Devnet333);
+
+    // Header on top of prev with the devnet powLimit, mined by trying nonces
+    fn mine(prev: UInt256, timestamp: u32) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 0x20000000,
+            prev_block_hash: prev,
+            merkle_root: UInt256([timestamp as u8; 32]),
+            timestamp,
+            bits: 0x207fffff,
+            nonce: 0,
+        };
+        while !header.has_valid_proof_of_work(CHAIN_TYPE) {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    fn extend(chain: &mut HeaderChain, from: UInt256, count: u32, seed: u32) -> Vec<UInt256> {
+        let mut hashes = vec![];
+        let mut prev = from;
+        for i in 0..count {
+            let header = mine(prev, seed + i);
+            chain.add_header(header).expect("header should be connected");
+            prev = header.block_hash();
+            hashes.push(prev);
+        }
+        hashes
+    }
+
+    #[test]
+    fn headers_are_connected_and_reorganized() {
+        let base = mine(UInt256([0u8; 32]), 1000);
+        let mut chain = HeaderChain::new(CHAIN_TYPE, base, 100);
+        let main = extend(&mut chain, base.block_hash(), 4, 2000);
+        assert_eq!(chain.tip(), Block { height: 104, hash: main[3] });
+        assert_eq!(chain.height_for(main[1]), 102);
+        assert_eq!(chain.height_for(UInt256([9u8; 32])), u32::MAX);
+        assert_eq!(chain.block_height_lookup()(base.block_hash()), 100);
+        assert_eq!(chain.hash_at(101), Some(main[0]));
+        assert_eq!(chain.hash_at(99), None);
+
+        let header = mine(main[3], 2003);
+        assert_eq!(chain.add_header(header).unwrap().unwrap().tip.height, 105);
+        assert_eq!(chain.add_header(header), Err(HeaderChainError::DuplicateHeader));
+        let orphan = mine(UInt256([9u8; 32]), 2004);
+        assert_eq!(chain.add_header(orphan), Err(HeaderChainError::UnknownParent));
+        let mut invalid = mine(main[0], 2005);
+        invalid.bits = 0x03000001;
+        assert_eq!(chain.add_header(invalid), Err(HeaderChainError::InvalidProofOfWork));
+
+        // a fork from height 102 with as much work doesn't replace the best chain
+        let fork = extend(&mut chain, main[1], 3, 3000);
+        assert_eq!(chain.tip().hash, header.block_hash());
+        assert_eq!(chain.height_for(fork[2]), 105);
+        assert!(!chain.is_in_best_chain(fork[0]));
+        // one more header gives it the most work
+        let fork_tip = mine(fork[2], 3003);
+        let change = chain.add_header(fork_tip).unwrap().expect("the fork should become best");
+        assert_eq!(change.tip, Block { height: 106, hash: fork_tip.block_hash() });
+        assert_eq!(
+            change.disconnected,
+            vec![
+                Block { height: 105, hash: header.block_hash() },
+                Block { height: 104, hash: main[3] },
+                Block { height: 103, hash: main[2] },
+            ]
+        );
+        assert_eq!(chain.hash_at(103), Some(fork[0]));
+        assert!(chain.is_in_best_chain(main[1]));
+        assert!(!chain.is_in_best_chain(main[2]));
+        assert_eq!(chain.height_for(main[3]), 104);
+    }
+
+    #[test]
+    fn height_past_u32_max_is_rejected() {
+        let base = mine(UInt256([0u8; 32]), 1000);
+        let mut chain = HeaderChain::new(CHAIN_TYPE, base, u32::MAX);
+        let header = mine(base.block_hash(), 2000);
+        assert_eq!(chain.add_header(header), Err(HeaderChainError::HeightOverflow));
+        assert_eq!(chain.tip(), Block { height: u32::MAX, hash: base.block_hash() });
+    }
+}
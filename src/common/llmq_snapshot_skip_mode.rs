@@ -9,14 +9,14 @@ pub enum LLMQSnapshotSkipMode {
     // Skip the first entry of the list.
     // The following entries contain the relative position of subsequent skips.
     // For example, if during the initialization phase you skip entries x, y and z of the masternode
-    // list, the skip list will contain x, y-x and z-y in this mode.
+    // list, the skip list will contain x, y-x and z-x in this mode.
     SkipFirst = 1,
     // Contains the entries which were not skipped.
-    // This is better when there are many skips.
-    // Mode 2 is more efficient and should be used when 3/4*quorumSize ≥ 1/2*masternodeNb or
-    // quorumsize ≥ 2/3*masternodeNb
+    // The DIP suggests it when 3/4*quorumSize ≥ 1/2*masternodeNb or quorumsize ≥ 2/3*masternodeNb,
+    // but Dash Core never builds it and returns empty quarters for it
     SkipExcept = 2,
     // Every node was skipped. The skip list is empty. DKG sessions were not attempted.
+    // Dash Core never builds it either
     SkipAll = 3,
 }
 impl From<u32> for LLMQSnapshotSkipMode {
@@ -297,7 +297,7 @@ impl From<LLMQType> for u8 {
 impl<'a> TryRead<'a, Endian> for LLMQType {
     fn try_read(bytes: &'a [u8], endian: Endian) -> byte::Result<(Self, usize)> {
         let offset = &mut 0;
-        let orig = bytes.read_with::<u8>(offset, endian).unwrap();
+        let orig = bytes.read_with::<u8>(offset, endian)?;
         let llmq_type = LLMQType::from(orig);
         Ok((llmq_type, 1))
     }
@@ -0,0 +1,32 @@
+use byte::ctx::Endian;
+use byte::{BytesExt, TryRead, LE};
+
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd, Hash)]
+pub enum MasternodeType {
+    #[default]
+    Regular = 0,
+    // Evolution masternode (formerly high performance, HPMN) also serving Dash Platform
+    Evo = 1,
+}
+
+impl TryFrom<u16> for MasternodeType {
+    type Error = byte::Error;
+
+    fn try_from(orig: u16) -> Result<Self, Self::Error> {
+        match orig {
+            0 => Ok(MasternodeType::Regular),
+            1 => Ok(MasternodeType::Evo),
+            _ => Err(byte::Error::BadInput {
+                err: "unknown masternode type",
+            }),
+        }
+    }
+}
+
+impl<'a> TryRead<'a, Endian> for MasternodeType {
+    fn try_read(bytes: &'a [u8], _endian: Endian) -> byte::Result<(Self, usize)> {
+        let orig = bytes.read_with::<u16>(&mut 0, LE)?;
+        Ok((MasternodeType::try_from(orig)?, 2))
+    }
+}
@@ -1,21 +1,44 @@
+use dash_spv_primitives::consensus::encode::VarInt;
 use dash_spv_primitives::consensus::Encodable;
 use dash_spv_primitives::crypto::byte_util::BytesDecodable;
 use dash_spv_primitives::crypto::{UInt256, VarBytes};
-use dash_spv_primitives::crypto::var_array::VarArray;
 use dash_spv_primitives::hashes::{sha256d, Hash};
 use byte::BytesExt;
+use crate::error::{read_field, Error};
+
+// Dash Core MaxBlockSize() over the lower bound of a serialized transaction size
+pub const MAX_TRANSACTIONS_IN_BLOCK: u32 = 2_000_000 / 60;
 
 #[inline]
-fn ceil_log2(mut x: i32) -> i32 {
-    let mut r = if x & (x - 1) != 0 { 1 } else { 0 };
-    loop {
-        x >>= 1;
-        if x == 0 {
-            break;
-        }
-        r += 1;
+fn ceil_log2(x: u32) -> i32 {
+    if x <= 1 {
+        0
+    } else {
+        (u32::BITS - (x - 1).leading_zeros()) as i32
     }
-    r
+}
+
+/// Reasons a partial merkle tree is rejected, as in CPartialMerkleTree::ExtractMatches
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MerkleTreeError {
+    // A block has at least the coinbase transaction
+    NoTransactions,
+    // More transactions than fit in a block, which also bounds the tree height
+    TooManyTransactions,
+    // There can't be more hashes than transactions
+    TooManyHashes,
+    // Every hash needs at least one flag bit
+    NotEnoughFlags,
+    // The traversal ran out of flag bits
+    MissingFlags,
+    // The traversal ran out of hashes
+    MissingHashes,
+    // Flag bytes were left after the traversal
+    UnusedFlags,
+    // Hashes were left after the traversal
+    UnusedHashes,
+    // Both children of an inner node have the same hash (CVE-2012-2459)
+    DuplicateHashes,
 }
 
 #[derive(Clone, Debug)]
@@ -25,40 +48,142 @@ pub struct MerkleTree<'a> {
     pub flags: &'a [u8],
 }
 
+/// MerkleTree owning its flags, as produced when building a partial tree
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OwnedMerkleTree {
+    pub tree_element_count: u32,
+    pub hashes: Vec<UInt256>,
+    pub flags: Vec<u8>,
+}
+
+fn tree_width(tree_element_count: u32, height: u32) -> u64 {
+    (tree_element_count as u64 + (1u64 << height) - 1) >> height
+}
+
+fn tree_height(tree_element_count: u32) -> u32 {
+    let mut height = 0;
+    while tree_width(tree_element_count, height) > 1 {
+        height += 1;
+    }
+    height
+}
+
+fn parent_hash(left: UInt256, right: UInt256) -> UInt256 {
+    let mut buffer: Vec<u8> = Vec::with_capacity(64);
+    left.consensus_encode(&mut buffer).unwrap();
+    right.consensus_encode(&mut buffer).unwrap();
+    UInt256(sha256d::Hash::hash(&buffer).into_inner())
+}
+
+/// Hash of the node at position of the tree level height, as CPartialMerkleTree::CalcHash
+fn node_hash(height: u32, position: u32, hashes: &[UInt256]) -> UInt256 {
+    if height == 0 {
+        return hashes[position as usize];
+    }
+    let left = node_hash(height - 1, position * 2, hashes);
+    let right = if ((position * 2 + 1) as u64) < tree_width(hashes.len() as u32, height - 1) {
+        node_hash(height - 1, position * 2 + 1, hashes)
+    } else {
+        left
+    };
+    parent_hash(left, right)
+}
+
+impl OwnedMerkleTree {
+    /// Partial tree of the block transactions proving the ones matching is_match,
+    /// as CPartialMerkleTree does for merkleblock messages
+    pub fn from_transaction_hashes(
+        transaction_hashes: &[UInt256],
+        is_match: impl Fn(&UInt256) -> bool,
+    ) -> Self {
+        let tree_element_count = transaction_hashes.len() as u32;
+        if tree_element_count == 0 {
+            return Self::default();
+        }
+        let matches = transaction_hashes.iter().map(is_match).collect::<Vec<_>>();
+        let mut hashes = Vec::new();
+        let mut bits = Vec::new();
+        Self::traverse_and_build(
+            tree_height(tree_element_count),
+            0,
+            transaction_hashes,
+            &matches,
+            &mut hashes,
+            &mut bits,
+        );
+        let mut flags = vec![0u8; bits.len().div_ceil(8)];
+        for (i, bit) in bits.into_iter().enumerate() {
+            flags[i / 8] |= (bit as u8) << (i % 8);
+        }
+        Self {
+            tree_element_count,
+            hashes,
+            flags,
+        }
+    }
+
+    fn traverse_and_build(
+        height: u32,
+        position: u32,
+        transaction_hashes: &[UInt256],
+        matches: &[bool],
+        hashes: &mut Vec<UInt256>,
+        bits: &mut Vec<bool>,
+    ) {
+        let first = (position as usize) << height;
+        let last = (((position + 1) as usize) << height).min(matches.len());
+        let is_parent_of_match = matches[first..last].iter().any(|m| *m);
+        bits.push(is_parent_of_match);
+        if height == 0 || !is_parent_of_match {
+            hashes.push(node_hash(height, position, transaction_hashes));
+        } else {
+            Self::traverse_and_build(
+                height - 1,
+                position * 2,
+                transaction_hashes,
+                matches,
+                hashes,
+                bits,
+            );
+            let tree_element_count = transaction_hashes.len() as u32;
+            if ((position * 2 + 1) as u64) < tree_width(tree_element_count, height - 1) {
+                Self::traverse_and_build(
+                    height - 1,
+                    position * 2 + 1,
+                    transaction_hashes,
+                    matches,
+                    hashes,
+                    bits,
+                );
+            }
+        }
+    }
+
+    pub fn as_tree(&self) -> MerkleTree<'_> {
+        MerkleTree {
+            tree_element_count: self.tree_element_count,
+            hashes: self.hashes.clone(),
+            flags: &self.flags,
+        }
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        self.as_tree().to_data()
+    }
+}
+
 impl<'a> MerkleTree<'a> {
 
     pub fn has_root(&self, desired_merkle_root: UInt256) -> bool {
         if self.tree_element_count == 0 {
             return true;
         }
-        if let Some(root) = self.merkle_root() {
-            if root == desired_merkle_root {
-                return true;
-            }
-        }
-        false
+        self.merkle_root() == Ok(desired_merkle_root)
     }
 
-    pub fn merkle_root(&self) -> Option<UInt256> {
-        let hash_idx = &mut 0;
-        let flag_idx = &mut 0;
-        self.walk_hash_idx(
-            hash_idx,
-            flag_idx,
-            0,
-            |hash, _flag| hash,
-            |left, right| {
-                let mut buffer: Vec<u8> = Vec::with_capacity(64);
-                left.consensus_encode(&mut buffer).unwrap();
-                right
-                    .unwrap_or(left)
-                    .consensus_encode(&mut buffer)
-                    .unwrap();
-                let hash = sha256d::Hash::hash(&buffer);
-                let value = hash.into_inner();
-                Some(UInt256(value))
-            },
-        )
+    /// Root of the partial tree, walked as in CPartialMerkleTree
+    pub fn merkle_root(&self) -> Result<UInt256, MerkleTreeError> {
+        self.extract_matches().map(|(root, _)| root)
     }
 
     pub fn walk_hash_idx<
@@ -79,14 +204,120 @@ impl<'a> MerkleTree<'a> {
         }
         let flag = self.flags[*flag_idx / 8] & (1 << (*flag_idx % 8)) != 0;
         *flag_idx += 1;
-        if !flag || depth == ceil_log2(self.tree_element_count as i32) {
+        if !flag || depth >= ceil_log2(self.tree_element_count) {
             let hash = self.hashes.get(*hash_idx).copied();
             *hash_idx += 1;
             return leaf(hash, flag);
         }
-        let left = self.walk_hash_idx(hash_idx, flag_idx, depth + 1, leaf, branch);
+        let left = self.walk_hash_idx(hash_idx, flag_idx, depth + 1, leaf, branch)?;
         let right = self.walk_hash_idx(hash_idx, flag_idx, depth + 1, leaf, branch);
-        branch(left.unwrap(), right)
+        branch(left, right)
+    }
+
+    fn tree_width(&self, height: u32) -> u64 {
+        tree_width(self.tree_element_count, height)
+    }
+
+    fn tree_height(&self) -> u32 {
+        tree_height(self.tree_element_count)
+    }
+
+    pub fn to_owned_tree(&self) -> OwnedMerkleTree {
+        OwnedMerkleTree {
+            tree_element_count: self.tree_element_count,
+            hashes: self.hashes.clone(),
+            flags: self.flags.to_vec(),
+        }
+    }
+
+    /// Serialization as in merkleblock messages, read back by decode
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let offset: &mut usize = &mut 0;
+        *offset += self.tree_element_count.consensus_encode(&mut buffer).unwrap();
+        *offset += VarInt(self.hashes.len() as u64)
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        for hash in &self.hashes {
+            *offset += hash.consensus_encode(&mut buffer).unwrap();
+        }
+        *offset += self.flags.to_vec().consensus_encode(&mut buffer).unwrap();
+        buffer
+    }
+
+    /// Walks the partial tree the way CPartialMerkleTree does and returns the computed root
+    /// together with the matched leaves as (position in block, hash) pairs.
+    /// Every flag byte and every hash has to be used by the traversal
+    pub fn extract_matches(&self) -> Result<(UInt256, Vec<(u32, UInt256)>), MerkleTreeError> {
+        if self.tree_element_count == 0 {
+            return Err(MerkleTreeError::NoTransactions);
+        }
+        if self.tree_element_count > MAX_TRANSACTIONS_IN_BLOCK {
+            return Err(MerkleTreeError::TooManyTransactions);
+        }
+        if self.hashes.len() > self.tree_element_count as usize {
+            return Err(MerkleTreeError::TooManyHashes);
+        }
+        if self.flags.len() * 8 < self.hashes.len() {
+            return Err(MerkleTreeError::NotEnoughFlags);
+        }
+        let mut matches = Vec::new();
+        let flag_idx = &mut 0;
+        let hash_idx = &mut 0;
+        let root =
+            self.traverse_and_extract(self.tree_height(), 0, flag_idx, hash_idx, &mut matches)?;
+        if flag_idx.div_ceil(8) != self.flags.len() {
+            return Err(MerkleTreeError::UnusedFlags);
+        }
+        if *hash_idx != self.hashes.len() {
+            return Err(MerkleTreeError::UnusedHashes);
+        }
+        Ok((root, matches))
+    }
+
+    fn traverse_and_extract(
+        &self,
+        height: u32,
+        position: u32,
+        flag_idx: &mut usize,
+        hash_idx: &mut usize,
+        matches: &mut Vec<(u32, UInt256)>,
+    ) -> Result<UInt256, MerkleTreeError> {
+        if *flag_idx >= self.flags.len() * 8 {
+            return Err(MerkleTreeError::MissingFlags);
+        }
+        let is_parent_of_match = self.flags[*flag_idx / 8] & (1 << (*flag_idx % 8)) != 0;
+        *flag_idx += 1;
+        if height == 0 || !is_parent_of_match {
+            let hash = *self
+                .hashes
+                .get(*hash_idx)
+                .ok_or(MerkleTreeError::MissingHashes)?;
+            *hash_idx += 1;
+            if height == 0 && is_parent_of_match {
+                matches.push((position, hash));
+            }
+            return Ok(hash);
+        }
+        let left =
+            self.traverse_and_extract(height - 1, position * 2, flag_idx, hash_idx, matches)?;
+        let right = if ((position * 2 + 1) as u64) < self.tree_width(height - 1) {
+            let right = self.traverse_and_extract(
+                height - 1,
+                position * 2 + 1,
+                flag_idx,
+                hash_idx,
+                matches,
+            )?;
+            // an identical right subtree gives the root of a different transaction list
+            if right == left {
+                return Err(MerkleTreeError::DuplicateHashes);
+            }
+            right
+        } else {
+            left
+        };
+        Ok(parent_hash(left, right))
     }
 }
 
@@ -102,14 +333,147 @@ impl<'a> BytesDecodable<'a, MerkleTree<'a>> for MerkleTree<'a> {
 impl<'a> byte::TryRead<'a, byte::ctx::Endian> for MerkleTree<'a> {
     fn try_read(bytes: &'a [u8], _endian: byte::ctx::Endian) -> byte::Result<(Self, usize)> {
         let offset = &mut 0;
-        let total_transactions = u32::from_bytes(bytes, offset).unwrap();
-        let merkle_hashes = VarArray::<UInt256>::from_bytes(bytes, offset).unwrap();
-        let merkle_flags_var_bytes = VarBytes::from_bytes(bytes, offset).unwrap();
-        let tree = MerkleTree {
+        let tree = Self::decode(bytes, offset)?;
+        Ok((tree, *offset))
+    }
+}
+
+impl<'a> MerkleTree<'a> {
+    pub fn decode(bytes: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        let total_transactions =
+            read_field::<u32>(bytes, offset, "MerkleTree", "total_transactions")?;
+        let merkle_hashes_count =
+            read_field::<VarInt>(bytes, offset, "MerkleTree", "merkle_hashes_count")?.0;
+        let mut merkle_hashes: Vec<UInt256> = Vec::new();
+        for _i in 0..merkle_hashes_count {
+            merkle_hashes.push(read_field::<UInt256>(bytes, offset, "MerkleTree", "merkle_hashes")?);
+        }
+        let merkle_flags_var_bytes =
+            read_field::<VarBytes>(bytes, offset, "MerkleTree", "merkle_flags")?;
+        Ok(MerkleTree {
             tree_element_count: total_transactions,
-            hashes: merkle_hashes.1,
+            hashes: merkle_hashes,
             flags: merkle_flags_var_bytes.1
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::merkle_tree::{
+        parent_hash as parent, MerkleTree, MerkleTreeError, OwnedMerkleTree,
+    };
+    use dash_spv_primitives::crypto::UInt256;
+
+    #[test]
+    fn matches_are_extracted_and_every_hash_and_flag_is_used() {
+        let (a, b, c) = (UInt256([1u8; 32]), UInt256([2u8; 32]), UInt256([3u8; 32]));
+        // 3 transactions with the second one matched: root, left node and b are flagged
+        let hashes = vec![a, b, parent(c, c)];
+        let flags = [0b01011];
+        let tree = MerkleTree {
+            tree_element_count: 3,
+            hashes: hashes.clone(),
+            flags: &flags,
         };
-        Ok((tree, *offset))
+        let root = parent(parent(a, b), parent(c, c));
+        assert_eq!(tree.extract_matches(), Ok((root, vec![(1, b)])));
+
+        let extra_flag_byte = [0b01011, 0];
+        let mut tree = tree.clone();
+        tree.flags = &extra_flag_byte;
+        assert_eq!(tree.extract_matches(), Err(MerkleTreeError::UnusedFlags));
+        tree.flags = &flags;
+        tree.hashes.pop();
+        assert_eq!(tree.extract_matches(), Err(MerkleTreeError::MissingHashes));
+        tree.hashes = vec![a, b, c, c];
+        assert_eq!(tree.extract_matches(), Err(MerkleTreeError::TooManyHashes));
+        tree.tree_element_count = 4;
+        assert_eq!(tree.extract_matches(), Err(MerkleTreeError::UnusedHashes));
+        tree.tree_element_count = 0;
+        assert_eq!(tree.extract_matches(), Err(MerkleTreeError::NoTransactions));
+        // 16 transactions with the first 3 matched need more than 8 flag bits
+        let all_flags = [0xff];
+        let tree = MerkleTree {
+            tree_element_count: 16,
+            hashes: hashes.clone(),
+            flags: &all_flags,
+        };
+        assert_eq!(tree.extract_matches(), Err(MerkleTreeError::MissingFlags));
+        let no_flags = [];
+        let tree = MerkleTree {
+            tree_element_count: 3,
+            hashes,
+            flags: &no_flags,
+        };
+        assert_eq!(tree.extract_matches(), Err(MerkleTreeError::NotEnoughFlags));
+    }
+
+    #[test]
+    fn built_partial_trees_prove_matches() {
+        for count in [1u8, 2, 3, 5, 7, 8, 9, 16, 17, 33] {
+            let hashes = (0..count).map(|i| UInt256([i; 32])).collect::<Vec<_>>();
+            let full = OwnedMerkleTree::from_transaction_hashes(&hashes, |_| false);
+            assert_eq!(full.hashes.len(), 1);
+            assert_eq!(full.flags, vec![0]);
+            let root = full.hashes[0];
+            let predicates: [fn(&UInt256) -> bool; 4] = [
+                |_| true,
+                |hash| hash.0[0] == 0,
+                |hash| hash.0[0] % 3 == 1,
+                |hash| hash.0[0] == 32,
+            ];
+            for is_match in predicates {
+                let tree = OwnedMerkleTree::from_transaction_hashes(&hashes, is_match);
+                let expected = hashes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, hash)| is_match(hash))
+                    .map(|(i, hash)| (i as u32, *hash))
+                    .collect::<Vec<_>>();
+                assert_eq!(tree.as_tree().extract_matches(), Ok((root, expected)));
+                let data = tree.to_data();
+                let offset = &mut 0;
+                let decoded = MerkleTree::decode(&data, offset).expect("tree should be parsed");
+                assert_eq!(*offset, data.len());
+                assert_eq!(decoded.to_owned_tree(), tree);
+            }
+        }
+        let empty = OwnedMerkleTree::from_transaction_hashes(&[], |_| true);
+        assert_eq!(empty.as_tree().extract_matches(), Err(MerkleTreeError::NoTransactions));
+    }
+
+    #[test]
+    fn malformed_and_mutated_trees_are_rejected() {
+        let hashes = (1..=3u8).map(|i| UInt256([i; 32])).collect::<Vec<_>>();
+        let tree = OwnedMerkleTree::from_transaction_hashes(&hashes, |_| true);
+        let root = tree.as_tree().merkle_root().expect("root should be computed");
+        assert!(tree.as_tree().has_root(root));
+        // duplicating the last transaction keeps the root (CVE-2012-2459)
+        let mut mutated_hashes = hashes.clone();
+        mutated_hashes.push(hashes[2]);
+        let mutated = OwnedMerkleTree::from_transaction_hashes(&mutated_hashes, |_| true);
+        assert_eq!(mutated.hashes.len(), 4);
+        assert_eq!(mutated.as_tree().merkle_root(), Err(MerkleTreeError::DuplicateHashes));
+        assert!(!mutated.as_tree().has_root(root));
+        // the root is only reported when the hashes and flags are all used
+        let mut short = tree.as_tree();
+        short.hashes.pop();
+        assert_eq!(short.merkle_root(), Err(MerkleTreeError::MissingHashes));
+        assert!(!short.has_root(root));
+        // the flags run out before reaching a leaf on the left, which used to panic
+        let all_flags = [0xff];
+        let deep = MerkleTree {
+            tree_element_count: 1024,
+            hashes: hashes.clone(),
+            flags: &all_flags,
+        };
+        let walked =
+            deep.walk_hash_idx(&mut 0, &mut 0, 0, |hash, _| hash, |left, _| Some(left));
+        assert_eq!(walked, None);
+        assert_eq!(deep.merkle_root(), Err(MerkleTreeError::MissingFlags));
+        let mut long = tree.as_tree();
+        long.tree_element_count = 100_000;
+        assert_eq!(long.merkle_root(), Err(MerkleTreeError::TooManyTransactions));
     }
 }
@@ -1,13 +1,20 @@
+pub mod base58;
 pub mod block;
+pub mod block_header;
 pub mod chain_type;
+pub mod header_chain;
 pub mod llmq_snapshot_skip_mode;
 pub mod llmq_type;
+pub mod masternode_type;
 pub mod merkle_tree;
 pub mod socket_address;
 
 pub use self::block::Block;
+pub use self::block_header::BlockHeader;
 pub use self::chain_type::ChainType;
+pub use self::header_chain::HeaderChain;
 pub use self::llmq_snapshot_skip_mode::LLMQSnapshotSkipMode;
 pub use self::llmq_type::{DKGParams, LLMQParams, LLMQType};
-pub use self::merkle_tree::MerkleTree;
+pub use self::masternode_type::MasternodeType;
+pub use self::merkle_tree::{MerkleTree, MerkleTreeError, OwnedMerkleTree};
 pub use self::socket_address::SocketAddress;
@@ -0,0 +1,116 @@
+use byte::ctx::{Bytes, Endian};
+use byte::{BytesExt, TryRead};
+
+/// Decoding failure for a field of a structure read from untrusted bytes.
+/// `offset` is the position in the decoded buffer where the field starts
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    // The buffer ended before the field could be read
+    Incomplete {
+        structure: &'static str,
+        field: &'static str,
+        offset: usize,
+    },
+    // The field was read but its value is not acceptable
+    BadInput {
+        structure: &'static str,
+        field: &'static str,
+        offset: usize,
+        reason: &'static str,
+    },
+}
+
+impl Error {
+    pub fn structure(&self) -> &'static str {
+        match self {
+            Error::Incomplete { structure, .. } | Error::BadInput { structure, .. } => structure,
+        }
+    }
+
+    pub fn field(&self) -> &'static str {
+        match self {
+            Error::Incomplete { field, .. } | Error::BadInput { field, .. } => field,
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        match self {
+            Error::Incomplete { offset, .. } | Error::BadInput { offset, .. } => *offset,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Incomplete {
+                structure,
+                field,
+                offset,
+            } => write!(f, "{}.{}: not enough bytes at offset {}", structure, field, offset),
+            Error::BadInput {
+                structure,
+                field,
+                offset,
+                reason,
+            } => write!(f, "{}.{}: {} at offset {}", structure, field, reason, offset),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for byte::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Incomplete { .. } => byte::Error::Incomplete,
+            Error::BadInput { reason, .. } => byte::Error::BadInput { err: reason },
+        }
+    }
+}
+
+pub(crate) fn field_error(
+    err: byte::Error,
+    structure: &'static str,
+    field: &'static str,
+    offset: usize,
+) -> Error {
+    match err {
+        byte::Error::Incomplete | byte::Error::BadOffset(_) => Error::Incomplete {
+            structure,
+            field,
+            offset,
+        },
+        byte::Error::BadInput { err } => Error::BadInput {
+            structure,
+            field,
+            offset,
+            reason: err,
+        },
+    }
+}
+
+pub(crate) fn read_field<'a, T: TryRead<'a, Endian>>(
+    bytes: &'a [u8],
+    offset: &mut usize,
+    structure: &'static str,
+    field: &'static str,
+) -> Result<T, Error> {
+    let start = *offset;
+    bytes
+        .read_with::<T>(offset, byte::LE)
+        .map_err(|err| field_error(err, structure, field, start))
+}
+
+pub(crate) fn read_bytes<'a>(
+    bytes: &'a [u8],
+    offset: &mut usize,
+    len: usize,
+    structure: &'static str,
+    field: &'static str,
+) -> Result<&'a [u8], Error> {
+    let start = *offset;
+    bytes
+        .read_with::<&[u8]>(offset, Bytes::Len(len))
+        .map_err(|err| field_error(err, structure, field, start))
+}
@@ -0,0 +1,141 @@
+use crate::common::{LLMQSnapshotSkipMode, LLMQType, MasternodeType, SocketAddress};
+use crate::llmq::{LLMQSnapshot, MNListDiff};
+use crate::masternode::masternode_entry::LEGACY_BLS_VERSION;
+use crate::masternode::{LLMQEntry, MasternodeEntry, MasternodeList};
+use crate::tx::{
+    CoinbaseTransaction, Transaction, TransactionInput, TransactionOutput, TransactionType,
+};
+use dash_spv_primitives::consensus::encode::VarInt;
+use dash_spv_primitives::crypto::byte_util::Reversable;
+use dash_spv_primitives::crypto::{UInt128, UInt160, UInt256, UInt384, UInt768};
+use std::collections::BTreeMap;
+
+pub fn coinbase_transaction(height: u32) -> CoinbaseTransaction {
+    CoinbaseTransaction {
+        base: Transaction {
+            inputs: vec![TransactionInput {
+                input_hash: UInt256([0u8; 32]),
+                index: u32::MAX,
+                script: None,
+                signature: Some(vec![0x03, 0x01, 0x02, 0x03]),
+                sequence: u32::MAX,
+                amount: None,
+            }],
+            outputs: vec![TransactionOutput {
+                amount: 500_000_000,
+                script: Some(vec![0x6a]),
+                address: None,
+            }],
+            lock_time: 0,
+            version: 3,
+            tx_hash: None,
+            tx_type: TransactionType::Coinbase,
+            extra_payload: None,
+            payload_offset: 0,
+            block_height: height,
+        },
+        coinbase_transaction_version: 2,
+        height,
+        merkle_root_mn_list: UInt256([1u8; 32]),
+        merkle_root_llmq_list: Some(UInt256([2u8; 32])),
+        best_cl_height_diff: 0,
+        best_cl_signature: UInt768::default(),
+        credit_pool_balance: 0,
+    }
+}
+
+pub fn masternode_entry(seed: u8) -> MasternodeEntry {
+    MasternodeEntry::new(
+        LEGACY_BLS_VERSION,
+        UInt256([seed; 32]),
+        UInt256([seed.wrapping_add(1); 32]),
+        SocketAddress {
+            ip_address: UInt128([seed; 16]),
+            port: 9999,
+        },
+        UInt160([seed; 20]),
+        UInt384([seed; 48]),
+        1,
+        MasternodeType::Regular,
+        0,
+        UInt160::default(),
+    )
+}
+
+pub fn llmq_entry(llmq_type: LLMQType, seed: u8) -> LLMQEntry {
+    LLMQEntry::new(
+        1,
+        llmq_type,
+        UInt256([seed; 32]),
+        None,
+        VarInt(4),
+        VarInt(4),
+        vec![0x0f],
+        vec![0x0f],
+        UInt384([seed; 48]),
+        UInt256([seed; 32]),
+        UInt768([seed; 96]),
+        UInt768([seed; 96]),
+    )
+}
+
+pub fn mn_list_diff(base_block_hash: UInt256, block_hash: UInt256, seed: u8) -> MNListDiff {
+    let coinbase_transaction = coinbase_transaction(seed as u32);
+    let added_or_modified_masternodes = (0..3u8)
+        .map(|i| masternode_entry(seed.wrapping_add(i)))
+        .map(|entry| {
+            (
+                entry.provider_registration_transaction_hash.clone().reversed(),
+                entry,
+            )
+        })
+        .collect::<BTreeMap<UInt256, MasternodeEntry>>();
+    let quorum = llmq_entry(LLMQType::Llmqtype50_60, seed);
+    let mut added_quorums = BTreeMap::new();
+    added_quorums.insert(
+        quorum.llmq_type,
+        BTreeMap::from([(quorum.llmq_hash, quorum)]),
+    );
+    let mut deleted_quorums = BTreeMap::new();
+    deleted_quorums.insert(LLMQType::Llmqtype400_60, vec![UInt256([seed; 32])]);
+    MNListDiff {
+        version: LEGACY_BLS_VERSION,
+        base_block_hash,
+        block_hash,
+        total_transactions: 1,
+        merkle_hashes: vec![UInt256([seed; 32])],
+        merkle_flags: vec![0x01],
+        coinbase_transaction,
+        deleted_masternode_hashes: vec![UInt256([seed.wrapping_add(100); 32])],
+        added_or_modified_masternodes,
+        deleted_quorums,
+        added_quorums,
+        quorums_cl_signatures: vec![],
+        added_masternodes_order: vec![],
+        deleted_quorums_order: vec![],
+        added_quorums_order: vec![],
+        base_block_height: 0,
+        block_height: 0,
+    }
+}
+
+pub fn llmq_snapshot(seed: u8) -> LLMQSnapshot {
+    LLMQSnapshot::new(
+        vec![seed, seed.wrapping_add(1)],
+        vec![seed as i32, 2, 3],
+        LLMQSnapshotSkipMode::SkipFirst,
+    )
+}
+
+/// List of confirmed masternodes made from the given seeds
+pub fn masternode_list(block_hash: UInt256, block_height: u32, seeds: &[u8]) -> MasternodeList {
+    let masternodes = seeds
+        .iter()
+        .map(|seed| {
+            let mut entry = masternode_entry(*seed);
+            entry.update_with_block_height(1);
+            (entry.provider_registration_transaction_hash.clone().reversed(), entry)
+        })
+        .collect();
+    MasternodeList::new(masternodes, BTreeMap::new(), block_hash, block_height, false)
+}
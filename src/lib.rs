@@ -1,8 +1,12 @@
 pub mod common;
+pub mod error;
 pub mod llmq;
 pub mod masternode;
 pub mod tx;
 
+#[cfg(test)]
+mod fixtures;
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -1,16 +1,43 @@
-use crate::common::LLMQType;
-use crate::masternode::{LLMQEntry, MasternodeEntry};
+use crate::common::{LLMQType, MerkleTree};
+use crate::error::{read_field, Error};
+use crate::masternode::masternode_entry::LEGACY_BLS_VERSION;
+use crate::masternode::{LLMQEntry, MasternodeEntry, MasternodeList};
 use crate::tx::CoinbaseTransaction;
 use dash_spv_primitives::consensus::encode::VarInt;
-use dash_spv_primitives::crypto::byte_util::{BytesDecodable, Reversable};
-use dash_spv_primitives::crypto::var_array::VarArray;
-use dash_spv_primitives::crypto::UInt256;
+use dash_spv_primitives::consensus::{Encodable, WriteExt};
+use dash_spv_primitives::crypto::byte_util::Reversable;
+use dash_spv_primitives::crypto::{UInt256, UInt768};
 use dash_spv_primitives::hashes::hex::ToHex;
-use std::collections::BTreeMap;
-use byte::BytesExt;
+use dash_spv_primitives::hashes::{sha256d, Hash};
+use std::collections::{BTreeMap, BTreeSet};
+
+// From this protocol version on, the diff carries nVersion right after the coinbase
+pub const BLS_SCHEME_PROTO_VERSION: u32 = 70225;
+// From this protocol version on, nVersion is the first field of the diff
+pub const MNLISTDIFF_VERSION_ORDER: u32 = 70229;
+// From this protocol version on, the diff ends with the chainlock signatures of the quorums
+pub const MNLISTDIFF_CHAINLOCKS_PROTO_VERSION: u32 = 70230;
+
+/// Result of checking that the diff's coinbase is committed to by the block it names
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MerkleProofVerification {
+    // The partial merkle tree could be walked with the given hashes and flags
+    pub has_valid_merkle_tree: bool,
+    // The coinbase txid is the matched leaf at position 0
+    pub has_found_coinbase: bool,
+    // The root computed from the tree equals the block header merkle root
+    pub has_valid_merkle_root: bool,
+}
+
+impl MerkleProofVerification {
+    pub fn is_valid(&self) -> bool {
+        self.has_valid_merkle_tree && self.has_found_coinbase && self.has_valid_merkle_root
+    }
+}
 
 #[derive(Clone)]
 pub struct MNListDiff {
+    pub version: u16,
     pub base_block_hash: UInt256,
     pub block_hash: UInt256,
     pub total_transactions: u32,
@@ -23,6 +50,15 @@ pub struct MNListDiff {
     pub added_or_modified_masternodes: BTreeMap<UInt256, MasternodeEntry>,
     pub deleted_quorums: BTreeMap<LLMQType, Vec<UInt256>>,
     pub added_quorums: BTreeMap<LLMQType, BTreeMap<UInt256, LLMQEntry>>,
+    // Chainlock signatures with the indexes, in the new quorums list, of the quorums
+    // whose cycle base block they sign (quorumsCLSigs), in message order
+    pub quorums_cl_signatures: Vec<(UInt768, Vec<u16>)>,
+    // Keys of the added or modified masternodes in message order
+    pub added_masternodes_order: Vec<UInt256>,
+    // Deleted quorums in message order
+    pub deleted_quorums_order: Vec<(LLMQType, UInt256)>,
+    // Added quorums in message order
+    pub added_quorums_order: Vec<(LLMQType, UInt256)>,
     pub base_block_height: u32,
     pub block_height: u32,
 }
@@ -30,6 +66,7 @@ pub struct MNListDiff {
 impl std::fmt::Debug for MNListDiff {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("MNListDiff")
+            .field("version", &self.version)
             .field("base_block_hash", &self.base_block_hash)
             .field("block_hash", &self.block_hash)
             .field("total_transactions", &self.total_transactions)
@@ -44,6 +81,10 @@ impl std::fmt::Debug for MNListDiff {
             )
             .field("deleted_quorums", &self.deleted_quorums)
             .field("added_quorums", &self.added_quorums)
+            .field("quorums_cl_signatures", &self.quorums_cl_signatures)
+            .field("added_masternodes_order", &self.added_masternodes_order)
+            .field("deleted_quorums_order", &self.deleted_quorums_order)
+            .field("added_quorums_order", &self.added_quorums_order)
             .field("base_block_height", &self.base_block_height)
             .field("block_height", &self.block_height)
             .finish()
@@ -51,84 +92,680 @@ impl std::fmt::Debug for MNListDiff {
 }
 
 impl MNListDiff {
+    /// Decodes a diff in the layout without version fields
     pub fn new<F: Fn(UInt256) -> u32>(
         message: &[u8],
         offset: &mut usize,
         block_height_lookup: F,
-    ) -> Option<Self> {
-        let base_block_hash = UInt256::from_bytes(message, offset)?;
-        let block_hash = UInt256::from_bytes(message, offset)?;
+    ) -> Result<Self, Error> {
+        Self::new_with_protocol_version(message, offset, 0, block_height_lookup)
+    }
+
+    /// Decodes a diff sent with the given protocol version
+    pub fn new_with_protocol_version<F: Fn(UInt256) -> u32>(
+        message: &[u8],
+        offset: &mut usize,
+        protocol_version: u32,
+        block_height_lookup: F,
+    ) -> Result<Self, Error> {
+        let mut version = LEGACY_BLS_VERSION;
+        if protocol_version >= MNLISTDIFF_VERSION_ORDER {
+            version = read_field::<u16>(message, offset, "MNListDiff", "version")?;
+        }
+        let base_block_hash =
+            read_field::<UInt256>(message, offset, "MNListDiff", "base_block_hash")?;
+        let block_hash = read_field::<UInt256>(message, offset, "MNListDiff", "block_hash")?;
         let base_block_height = block_height_lookup(base_block_hash);
         let block_height = block_height_lookup(block_hash);
-        let total_transactions = u32::from_bytes(message, offset)?;
-        let merkle_hashes = VarArray::<UInt256>::from_bytes(message, offset)?;
-        let merkle_flags_count = VarInt::from_bytes(message, offset)?.0 as usize;
-        let merkle_flags: &[u8] = match message.read_with(offset, byte::ctx::Bytes::Len(merkle_flags_count)) {
-            Ok(data) => data,
-            Err(_err) => { return None; },
-        };
-        let coinbase_transaction = CoinbaseTransaction::from_bytes(message, offset)?;
-        let deleted_masternode_count = VarInt::from_bytes(message, offset)?.0;
-        let mut deleted_masternode_hashes: Vec<UInt256> =
-            Vec::with_capacity(deleted_masternode_count as usize);
+        let merkle_tree = MerkleTree::decode(message, offset)?;
+        let coinbase_transaction = CoinbaseTransaction::decode(message, offset)?;
+        if (BLS_SCHEME_PROTO_VERSION..MNLISTDIFF_VERSION_ORDER).contains(&protocol_version) {
+            version = read_field::<u16>(message, offset, "MNListDiff", "version")?;
+        }
+        let deleted_masternode_count =
+            read_field::<VarInt>(message, offset, "MNListDiff", "deleted_masternode_count")?.0;
+        let mut deleted_masternode_hashes: Vec<UInt256> = Vec::new();
         for _i in 0..deleted_masternode_count {
-            deleted_masternode_hashes.push(UInt256::from_bytes(message, offset)?);
-        }
-        let added_masternode_count = VarInt::from_bytes(message, offset)?.0;
-        let added_or_modified_masternodes: BTreeMap<UInt256, MasternodeEntry> = (0
-            ..added_masternode_count)
-            .into_iter()
-            .filter_map(|_i| {
-                // assert_eq!(message.len(), MN_ENTRY_PAYLOAD_LENGTH);
-                let mut entry = MasternodeEntry::from_bytes(message, offset)?;
-                entry.update_with_block_height(block_height);
-                Some(entry)
-            })
-            .fold(BTreeMap::new(), |mut acc, entry| {
-                let hash = entry
-                    .provider_registration_transaction_hash
-                    .clone()
-                    .reversed();
-                acc.insert(hash, entry);
-                acc
-            });
-
+            deleted_masternode_hashes.push(read_field::<UInt256>(
+                message,
+                offset,
+                "MNListDiff",
+                "deleted_masternode_hashes",
+            )?);
+        }
+        let added_masternode_count =
+            read_field::<VarInt>(message, offset, "MNListDiff", "added_masternode_count")?.0;
+        let mut added_or_modified_masternodes: BTreeMap<UInt256, MasternodeEntry> = BTreeMap::new();
+        let mut added_masternodes_order: Vec<UInt256> = Vec::new();
+        for _i in 0..added_masternode_count {
+            let mut entry = MasternodeEntry::decode_with_protocol_version(
+                message,
+                offset,
+                protocol_version,
+                version,
+            )?;
+            entry.update_with_block_height(block_height);
+            let hash = entry
+                .provider_registration_transaction_hash
+                .clone()
+                .reversed();
+            added_masternodes_order.push(hash);
+            added_or_modified_masternodes.insert(hash, entry);
+        }
         let mut deleted_quorums: BTreeMap<LLMQType, Vec<UInt256>> = BTreeMap::new();
         let mut added_quorums: BTreeMap<LLMQType, BTreeMap<UInt256, LLMQEntry>> = BTreeMap::new();
+        let mut deleted_quorums_order: Vec<(LLMQType, UInt256)> = Vec::new();
+        let mut added_quorums_order: Vec<(LLMQType, UInt256)> = Vec::new();
         let quorums_active = coinbase_transaction.coinbase_transaction_version >= 2;
         if quorums_active {
-            let deleted_quorums_count = VarInt::from_bytes(message, offset)?.0;
+            let deleted_quorums_count =
+                read_field::<VarInt>(message, offset, "MNListDiff", "deleted_quorums_count")?.0;
             for _i in 0..deleted_quorums_count {
-                let llmq_type = LLMQType::from_bytes(message, offset)?;
-                let llmq_hash = UInt256::from_bytes(message, offset)?;
+                let llmq_type =
+                    read_field::<LLMQType>(message, offset, "MNListDiff", "deleted_quorum_type")?;
+                let llmq_hash =
+                    read_field::<UInt256>(message, offset, "MNListDiff", "deleted_quorum_hash")?;
+                deleted_quorums_order.push((llmq_type, llmq_hash));
                 deleted_quorums
                     .entry(llmq_type)
                     .or_insert_with(Vec::new)
                     .push(llmq_hash);
             }
-            let added_quorums_count = VarInt::from_bytes(message, offset)?.0;
+            let added_quorums_count =
+                read_field::<VarInt>(message, offset, "MNListDiff", "added_quorums_count")?.0;
             for _i in 0..added_quorums_count {
-                if let Some(entry) = LLMQEntry::from_bytes(message, offset) {
-                    added_quorums
-                        .entry(entry.llmq_type)
-                        .or_insert_with(BTreeMap::new)
-                        .insert(entry.llmq_hash, entry);
+                let entry = LLMQEntry::decode(message, offset)?;
+                added_quorums_order.push((entry.llmq_type, entry.llmq_hash));
+                added_quorums
+                    .entry(entry.llmq_type)
+                    .or_insert_with(BTreeMap::new)
+                    .insert(entry.llmq_hash, entry);
+            }
+        }
+        let mut quorums_cl_signatures: Vec<(UInt768, Vec<u16>)> = Vec::new();
+        if protocol_version >= MNLISTDIFF_CHAINLOCKS_PROTO_VERSION {
+            let signatures_count =
+                read_field::<VarInt>(message, offset, "MNListDiff", "quorums_cl_sigs_count")?.0;
+            for _i in 0..signatures_count {
+                let signature =
+                    read_field::<UInt768>(message, offset, "MNListDiff", "quorums_cl_sig")?;
+                let index_count = read_field::<VarInt>(
+                    message,
+                    offset,
+                    "MNListDiff",
+                    "quorums_cl_sig_index_count",
+                )?
+                .0;
+                let mut indexes: Vec<u16> = Vec::new();
+                for _j in 0..index_count {
+                    indexes.push(read_field::<u16>(
+                        message,
+                        offset,
+                        "MNListDiff",
+                        "quorums_cl_sig_index",
+                    )?);
                 }
+                quorums_cl_signatures.push((signature, indexes));
             }
         }
-        Some(Self {
+        Ok(Self {
+            version,
             base_block_hash,
             block_hash,
-            total_transactions,
-            merkle_hashes: merkle_hashes.1,
-            merkle_flags: merkle_flags.to_vec(),
+            total_transactions: merkle_tree.tree_element_count,
+            merkle_hashes: merkle_tree.hashes,
+            merkle_flags: merkle_tree.flags.to_vec(),
             coinbase_transaction,
             deleted_masternode_hashes,
             added_or_modified_masternodes,
             deleted_quorums,
             added_quorums,
+            quorums_cl_signatures,
+            added_masternodes_order,
+            deleted_quorums_order,
+            added_quorums_order,
             base_block_height,
             block_height,
         })
     }
+
+    /// Checks that the coinbase transaction is included in the block `block_hash`
+    /// whose header commits to `block_merkle_root`
+    pub fn verify_merkle_proof(&self, block_merkle_root: UInt256) -> MerkleProofVerification {
+        let tree = MerkleTree {
+            tree_element_count: self.total_transactions,
+            hashes: self.merkle_hashes.clone(),
+            flags: &self.merkle_flags,
+        };
+        let (root, matches) = match tree.extract_matches() {
+            Ok(result) => result,
+            Err(_) => return MerkleProofVerification::default(),
+        };
+        let coinbase_hash = self.coinbase_transaction.base.tx_hash.unwrap_or_else(|| {
+            UInt256(sha256d::Hash::hash(&self.coinbase_transaction.to_data()).into_inner())
+        });
+        MerkleProofVerification {
+            has_valid_merkle_tree: true,
+            has_found_coinbase: matches
+                .first()
+                .map(|&(position, hash)| position == 0 && hash == coinbase_hash)
+                .unwrap_or(false),
+            has_valid_merkle_root: root == block_merkle_root,
+        }
+    }
+
+    /// Builds the diff which turns `base` into `target`, a full list diff with a zero base block
+    /// hash when base is the default (empty) list.
+    /// The coinbase and its merkle proof can't be derived from the lists so they're passed as is,
+    /// and the quorum chainlock signatures are left empty
+    pub fn between(
+        base: &MasternodeList,
+        target: &MasternodeList,
+        coinbase_transaction: CoinbaseTransaction,
+        total_transactions: u32,
+        merkle_hashes: Vec<UInt256>,
+        merkle_flags: Vec<u8>,
+    ) -> Self {
+        let deleted_masternode_hashes: Vec<UInt256> = base
+            .masternodes
+            .keys()
+            .filter(|hash| !target.masternodes.contains_key(*hash))
+            .map(|hash| hash.clone().reversed())
+            .collect();
+        let added_or_modified_masternodes: BTreeMap<UInt256, MasternodeEntry> = target
+            .masternodes
+            .iter()
+            .filter(|(hash, entry)| match base.masternodes.get(*hash) {
+                Some(old) => old.entry_hash != entry.entry_hash,
+                None => true,
+            })
+            .map(|(hash, entry)| (*hash, entry.clone()))
+            .collect();
+        let mut deleted_quorums: BTreeMap<LLMQType, Vec<UInt256>> = BTreeMap::new();
+        let mut added_quorums: BTreeMap<LLMQType, BTreeMap<UInt256, LLMQEntry>> = BTreeMap::new();
+        for (llmq_type, entries) in &base.quorums {
+            let target_entries = target.quorums.get(llmq_type);
+            for (hash, entry) in entries {
+                let is_kept = target_entries
+                    .and_then(|map| map.get(hash))
+                    .map(|target_entry| target_entry.entry_hash == entry.entry_hash)
+                    .unwrap_or(false);
+                if !is_kept {
+                    deleted_quorums
+                        .entry(*llmq_type)
+                        .or_default()
+                        .push(*hash);
+                }
+            }
+        }
+        for (llmq_type, entries) in &target.quorums {
+            let base_entries = base.quorums.get(llmq_type);
+            for (hash, entry) in entries {
+                let is_known = base_entries
+                    .and_then(|map| map.get(hash))
+                    .map(|base_entry| base_entry.entry_hash == entry.entry_hash)
+                    .unwrap_or(false);
+                if !is_known {
+                    added_quorums
+                        .entry(*llmq_type)
+                        .or_default()
+                        .insert(*hash, entry.clone());
+                }
+            }
+        }
+        Self {
+            version: LEGACY_BLS_VERSION,
+            base_block_hash: if base.block_hash == UInt256::MAX {
+                UInt256::MIN
+            } else {
+                base.block_hash
+            },
+            block_hash: target.block_hash,
+            total_transactions,
+            merkle_hashes,
+            merkle_flags,
+            coinbase_transaction,
+            deleted_masternode_hashes,
+            added_or_modified_masternodes,
+            deleted_quorums,
+            added_quorums,
+            quorums_cl_signatures: vec![],
+            added_masternodes_order: vec![],
+            deleted_quorums_order: vec![],
+            added_quorums_order: vec![],
+            base_block_height: base.known_height,
+            block_height: target.known_height,
+        }
+    }
+
+    /// Encodes the diff in the layout without version fields
+    pub fn to_data(&self) -> Vec<u8> {
+        self.to_data_with_protocol_version(0)
+    }
+
+    /// Encodes the diff in `mnlistdiff` wire format for the given protocol version.
+    /// Entries are written in the order they were decoded in, so a decoded message is reproduced
+    /// byte for byte. Entries missing from that order come after, masternodes ordered by their
+    /// pro_reg_tx_hash and quorums by type and hash
+    pub fn to_data_with_protocol_version(&self, protocol_version: u32) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let offset: &mut usize = &mut 0;
+        if protocol_version >= MNLISTDIFF_VERSION_ORDER {
+            *offset += self.version.consensus_encode(&mut buffer).unwrap();
+        }
+        *offset += self.base_block_hash.consensus_encode(&mut buffer).unwrap();
+        *offset += self.block_hash.consensus_encode(&mut buffer).unwrap();
+        *offset += self.total_transactions.consensus_encode(&mut buffer).unwrap();
+        *offset += VarInt(self.merkle_hashes.len() as u64)
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        for hash in &self.merkle_hashes {
+            *offset += hash.consensus_encode(&mut buffer).unwrap();
+        }
+        *offset += self.merkle_flags.consensus_encode(&mut buffer).unwrap();
+        let coinbase_data = self.coinbase_transaction.to_data();
+        buffer.emit_slice(&coinbase_data).unwrap();
+        *offset += coinbase_data.len();
+        if (BLS_SCHEME_PROTO_VERSION..MNLISTDIFF_VERSION_ORDER).contains(&protocol_version) {
+            *offset += self.version.consensus_encode(&mut buffer).unwrap();
+        }
+        *offset += VarInt(self.deleted_masternode_hashes.len() as u64)
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        for hash in &self.deleted_masternode_hashes {
+            *offset += hash.consensus_encode(&mut buffer).unwrap();
+        }
+        let mut written: BTreeSet<&UInt256> = BTreeSet::new();
+        let mut added_masternodes: Vec<&MasternodeEntry> = self
+            .added_masternodes_order
+            .iter()
+            .filter_map(|hash| self.added_or_modified_masternodes.get_key_value(hash))
+            .filter(|(hash, _)| written.insert(*hash))
+            .map(|(_, entry)| entry)
+            .collect();
+        let mut unordered_masternodes: Vec<&MasternodeEntry> = self
+            .added_or_modified_masternodes
+            .iter()
+            .filter(|(hash, _)| !written.contains(hash))
+            .map(|(_, entry)| entry)
+            .collect();
+        unordered_masternodes.sort_by(|e1, e2| {
+            e1.provider_registration_transaction_hash
+                .cmp(&e2.provider_registration_transaction_hash)
+        });
+        added_masternodes.extend(unordered_masternodes);
+        *offset += VarInt(added_masternodes.len() as u64)
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        for entry in added_masternodes {
+            let entry_data = entry.to_data_with_protocol_version(protocol_version);
+            buffer.emit_slice(&entry_data).unwrap();
+            *offset += entry_data.len();
+        }
+        if self.coinbase_transaction.coinbase_transaction_version >= 2 {
+            let mut remaining_deleted_quorums = self.deleted_quorums.clone();
+            let mut deleted_quorums: Vec<(LLMQType, UInt256)> = Vec::new();
+            for (llmq_type, hash) in &self.deleted_quorums_order {
+                if let Some(hashes) = remaining_deleted_quorums.get_mut(llmq_type) {
+                    if let Some(position) = hashes.iter().position(|h| h == hash) {
+                        deleted_quorums.push((*llmq_type, hashes.remove(position)));
+                    }
+                }
+            }
+            for (llmq_type, hashes) in remaining_deleted_quorums {
+                deleted_quorums.extend(hashes.into_iter().map(|hash| (llmq_type, hash)));
+            }
+            *offset += VarInt(deleted_quorums.len() as u64)
+                .consensus_encode(&mut buffer)
+                .unwrap();
+            for (llmq_type, hash) in deleted_quorums {
+                let llmq_u8: u8 = llmq_type.into();
+                *offset += llmq_u8.consensus_encode(&mut buffer).unwrap();
+                *offset += hash.consensus_encode(&mut buffer).unwrap();
+            }
+            let mut written: BTreeSet<(LLMQType, UInt256)> = BTreeSet::new();
+            let mut added_quorums: Vec<&LLMQEntry> = self
+                .added_quorums_order
+                .iter()
+                .filter_map(|(llmq_type, hash)| {
+                    self.added_quorums
+                        .get(llmq_type)
+                        .and_then(|entries| entries.get(hash))
+                })
+                .filter(|entry| written.insert((entry.llmq_type, entry.llmq_hash)))
+                .collect();
+            added_quorums.extend(
+                self.added_quorums
+                    .values()
+                    .flat_map(BTreeMap::values)
+                    .filter(|entry| !written.contains(&(entry.llmq_type, entry.llmq_hash))),
+            );
+            *offset += VarInt(added_quorums.len() as u64)
+                .consensus_encode(&mut buffer)
+                .unwrap();
+            for entry in added_quorums {
+                let entry_data = entry.to_data();
+                buffer.emit_slice(&entry_data).unwrap();
+                *offset += entry_data.len();
+            }
+        }
+        if protocol_version >= MNLISTDIFF_CHAINLOCKS_PROTO_VERSION {
+            *offset += VarInt(self.quorums_cl_signatures.len() as u64)
+                .consensus_encode(&mut buffer)
+                .unwrap();
+            for (signature, indexes) in &self.quorums_cl_signatures {
+                *offset += signature.consensus_encode(&mut buffer).unwrap();
+                *offset += VarInt(indexes.len() as u64)
+                    .consensus_encode(&mut buffer)
+                    .unwrap();
+                for index in indexes {
+                    *offset += index.consensus_encode(&mut buffer).unwrap();
+                }
+            }
+        }
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{Block, LLMQType, MasternodeType};
+    use crate::error::Error;
+    use crate::fixtures;
+    use crate::llmq::MNListDiff;
+    use crate::masternode::{MasternodeEntry, MasternodeList};
+    use dash_spv_primitives::crypto::byte_util::Reversable;
+    use dash_spv_primitives::crypto::{UInt160, UInt256, UInt384, UInt768};
+    use dash_spv_primitives::hashes::{sha256d, Hash};
+
+    #[test]
+    fn mn_list_diff_round_trip() {
+        let diff = fixtures::mn_list_diff(UInt256([0u8; 32]), UInt256([7u8; 32]), 10);
+        let data = diff.to_data();
+        let parsed = MNListDiff::new(&data, &mut 0, |_| 1).expect("diff should be parsed");
+        assert_eq!(parsed.added_or_modified_masternodes.len(), 3);
+        assert_eq!(parsed.to_data(), data);
+    }
+
+    #[test]
+    fn mn_list_diff_round_trip_keeps_wire_order() {
+        let mut diff = fixtures::mn_list_diff(UInt256([0u8; 32]), UInt256([7u8; 32]), 10);
+        for (llmq_type, seed) in [(LLMQType::Llmqtype50_60, 3), (LLMQType::Llmqtype400_60, 1)] {
+            let entry = fixtures::llmq_entry(llmq_type, seed);
+            diff.added_quorums
+                .entry(llmq_type)
+                .or_default()
+                .insert(entry.llmq_hash, entry);
+        }
+        diff.deleted_quorums.insert(
+            LLMQType::Llmqtype50_60,
+            vec![UInt256([9u8; 32]), UInt256([8u8; 32])],
+        );
+        // neither sorted by hash nor grouped by type, as Core sends them
+        diff.added_masternodes_order = diff
+            .added_or_modified_masternodes
+            .keys()
+            .rev()
+            .copied()
+            .collect();
+        diff.added_quorums_order = diff
+            .added_quorums
+            .values()
+            .flat_map(|entries| entries.values())
+            .map(|entry| (entry.llmq_type, entry.llmq_hash))
+            .rev()
+            .collect();
+        diff.deleted_quorums_order = vec![
+            (LLMQType::Llmqtype50_60, UInt256([9u8; 32])),
+            (LLMQType::Llmqtype400_60, UInt256([10u8; 32])),
+            (LLMQType::Llmqtype50_60, UInt256([8u8; 32])),
+        ];
+        let data = diff.to_data();
+        let parsed = MNListDiff::new(&data, &mut 0, |_| 1).expect("diff should be parsed");
+        assert_eq!(parsed.added_masternodes_order, diff.added_masternodes_order);
+        assert_eq!(parsed.added_quorums_order, diff.added_quorums_order);
+        assert_eq!(parsed.deleted_quorums_order, diff.deleted_quorums_order);
+        assert_eq!(parsed.to_data(), data);
+        // without an order, entries are sorted and the message differs
+        let mut sorted = parsed.clone();
+        sorted.added_masternodes_order.clear();
+        sorted.added_quorums_order.clear();
+        sorted.deleted_quorums_order.clear();
+        let sorted_data = sorted.to_data();
+        assert_eq!(sorted_data.len(), data.len());
+        assert_ne!(sorted_data, data);
+        let reparsed = MNListDiff::new(&sorted_data, &mut 0, |_| 1).unwrap();
+        assert_eq!(
+            reparsed.added_or_modified_masternodes,
+            parsed.added_or_modified_masternodes
+        );
+        assert_eq!(reparsed.added_quorums, parsed.added_quorums);
+        assert_eq!(reparsed.deleted_quorums, parsed.deleted_quorums);
+    }
+
+    #[test]
+    fn versioned_mn_list_diff_round_trip() {
+        let mut diff = fixtures::mn_list_diff(UInt256([0u8; 32]), UInt256([7u8; 32]), 10);
+        // entries of this protocol take the diff version which is sent after the coinbase
+        diff.version = 2;
+        let data = diff.to_data_with_protocol_version(70225);
+        assert_eq!(data.len(), diff.to_data().len() + 2);
+        let parsed = MNListDiff::new_with_protocol_version(&data, &mut 0, 70225, |_| 1)
+            .expect("diff should be parsed");
+        assert_eq!(parsed.version, 2);
+        assert!(parsed
+            .added_or_modified_masternodes
+            .values()
+            .all(|entry| entry.version == 2 && !entry.uses_legacy_bls()));
+        assert_eq!(parsed.to_data_with_protocol_version(70225), data);
+        // later on the diff version comes first and every entry carries its own
+        diff.version = 1;
+        let data = diff.to_data_with_protocol_version(70229);
+        assert_eq!(data.len(), diff.to_data().len() + 2 + 2 * 3);
+        let parsed = MNListDiff::new_with_protocol_version(&data, &mut 0, 70229, |_| 1)
+            .expect("diff should be parsed");
+        assert_eq!(parsed.to_data_with_protocol_version(70229), data);
+        assert_eq!(
+            parsed.added_or_modified_masternodes,
+            MNListDiff::new(&diff.to_data(), &mut 0, |_| 1)
+                .unwrap()
+                .added_or_modified_masternodes
+        );
+    }
+
+    #[test]
+    fn quorum_chainlock_signatures_round_trip() {
+        let mut diff = fixtures::mn_list_diff(UInt256([0u8; 32]), UInt256([7u8; 32]), 10);
+        diff.quorums_cl_signatures = vec![
+            (UInt768([6u8; 96]), vec![1, 2]),
+            (UInt768([5u8; 96]), vec![0]),
+        ];
+        let without_signatures = diff.to_data_with_protocol_version(70229);
+        let data = diff.to_data_with_protocol_version(70230);
+        // count, then signature, index count and u16 indexes for every entry
+        assert_eq!(data.len(), without_signatures.len() + 1 + (96 + 1 + 4) + (96 + 1 + 2));
+        assert_eq!(data[..without_signatures.len()], without_signatures[..]);
+        let parsed = MNListDiff::new_with_protocol_version(&data, &mut 0, 70230, |_| 1)
+            .expect("diff should be parsed");
+        assert_eq!(parsed.quorums_cl_signatures, diff.quorums_cl_signatures);
+        assert_eq!(parsed.to_data_with_protocol_version(70230), data);
+        // earlier protocols don't carry them
+        let parsed =
+            MNListDiff::new_with_protocol_version(&without_signatures, &mut 0, 70229, |_| 1)
+                .expect("diff should be parsed");
+        assert!(parsed.quorums_cl_signatures.is_empty());
+        // the list is required from this protocol on, even when empty
+        let err =
+            MNListDiff::new_with_protocol_version(&without_signatures, &mut 0, 70230, |_| 1)
+                .unwrap_err();
+        assert_eq!(err.field(), "quorums_cl_sigs_count");
+        let truncated = &data[..data.len() - 1];
+        let err = MNListDiff::new_with_protocol_version(truncated, &mut 0, 70230, |_| 1)
+            .unwrap_err();
+        assert_eq!(err.field(), "quorums_cl_sig_index");
+    }
+
+    #[test]
+    fn evonode_entries_round_trip() {
+        let mut diff = fixtures::mn_list_diff(UInt256([0u8; 32]), UInt256([7u8; 32]), 10);
+        diff.version = 2;
+        for (i, entry) in diff.added_or_modified_masternodes.values_mut().enumerate() {
+            let mn_type = if i == 0 { MasternodeType::Evo } else { MasternodeType::Regular };
+            *entry = MasternodeEntry::new(
+                2,
+                entry.provider_registration_transaction_hash,
+                entry.confirmed_hash,
+                entry.socket_address,
+                entry.key_id_voting,
+                entry.operator_public_key,
+                1,
+                mn_type,
+                443,
+                UInt160([9u8; 20]),
+            );
+        }
+        let data = diff.to_data_with_protocol_version(70228);
+        let parsed = MNListDiff::new_with_protocol_version(&data, &mut 0, 70228, |_| 1)
+            .expect("diff should be parsed");
+        assert_eq!(parsed.to_data_with_protocol_version(70228), data);
+        let evonodes: Vec<&MasternodeEntry> = parsed
+            .added_or_modified_masternodes
+            .values()
+            .filter(|entry| entry.is_evonode())
+            .collect();
+        assert_eq!(evonodes.len(), 1);
+        assert_eq!(evonodes[0].platform_http_port, 443);
+        assert_eq!(evonodes[0].platform_node_id, UInt160([9u8; 20]));
+        // platform fields are part of the entry hash
+        let mut entry = evonodes[0].clone();
+        entry.platform_http_port = 80;
+        assert_ne!(entry.payload_data(), evonodes[0].entry_hash);
+        assert_eq!(evonodes[0].payload_data(), evonodes[0].entry_hash);
+    }
+
+    #[test]
+    fn truncated_mn_list_diff_reports_failing_field() {
+        let diff = fixtures::mn_list_diff(UInt256([0u8; 32]), UInt256([7u8; 32]), 10);
+        let data = diff.to_data();
+        // cut in the middle of block_hash
+        let err = MNListDiff::new(&data[..40], &mut 0, |_| 1).unwrap_err();
+        assert_eq!(
+            err,
+            Error::Incomplete {
+                structure: "MNListDiff",
+                field: "block_hash",
+                offset: 32,
+            }
+        );
+        // cut right after the coinbase transaction
+        let coinbase_end = 32 + 32 + 4 + 1 + 32 + 2 + diff.coinbase_transaction.to_data().len();
+        let err = MNListDiff::new(&data[..coinbase_end], &mut 0, |_| 1).unwrap_err();
+        assert_eq!(err.field(), "deleted_masternode_count");
+        assert_eq!(err.offset(), coinbase_end);
+    }
+
+    #[test]
+    fn mn_list_diff_merkle_proof_verification() {
+        let mut diff = fixtures::mn_list_diff(UInt256([0u8; 32]), UInt256([7u8; 32]), 10);
+        let coinbase_hash =
+            UInt256(sha256d::Hash::hash(&diff.coinbase_transaction.to_data()).into_inner());
+        diff.merkle_hashes = vec![coinbase_hash];
+        assert!(diff.verify_merkle_proof(coinbase_hash).is_valid());
+        let report = diff.verify_merkle_proof(UInt256([3u8; 32]));
+        assert!(report.has_found_coinbase);
+        assert!(!report.has_valid_merkle_root);
+        // the coinbase is present in the hash list but not flagged as a match
+        diff.merkle_flags = vec![0x00];
+        let report = diff.verify_merkle_proof(coinbase_hash);
+        assert!(report.has_valid_merkle_tree);
+        assert!(!report.has_found_coinbase);
+    }
+
+    #[test]
+    fn diff_between_lists_is_applied_back_to_target() {
+        let mut base = fixtures::masternode_list(UInt256([1u8; 32]), 5, &[1, 2, 3]);
+        let q1 = fixtures::llmq_entry(LLMQType::Llmqtype50_60, 1);
+        let q2 = fixtures::llmq_entry(LLMQType::Llmqtype50_60, 2);
+        base.quorums.insert(
+            LLMQType::Llmqtype50_60,
+            [(q1.llmq_hash, q1.clone()), (q2.llmq_hash, q2)].into(),
+        );
+        let block = Block {
+            height: 6,
+            hash: UInt256([2u8; 32]),
+        };
+        let mut masternodes = base.masternodes.clone();
+        let deleted = fixtures::masternode_entry(1).provider_registration_transaction_hash;
+        masternodes.remove(&deleted.clone().reversed());
+        for entry in masternodes.values_mut() {
+            if entry.provider_registration_transaction_hash == UInt256([2u8; 32]) {
+                // the entry as known to a client which followed the chain up to block 6
+                let mut old = entry.clone();
+                entry.operator_public_key = UInt384([42u8; 48]);
+                entry.entry_hash = entry.payload_data();
+                entry.update_with_block_height(6);
+                entry.update_with_previous_entry(&mut old, block);
+                entry.known_confirmed_at_height = old.known_confirmed_at_height;
+            }
+        }
+        let mut added = fixtures::masternode_entry(4);
+        added.update_with_block_height(6);
+        masternodes.insert(added.provider_registration_transaction_hash.clone().reversed(), added);
+        let mut quorums = base.quorums.clone();
+        quorums.get_mut(&LLMQType::Llmqtype50_60).unwrap().remove(&q1.llmq_hash);
+        let q3 = fixtures::llmq_entry(LLMQType::Llmqtype60_75, 3);
+        quorums.insert(LLMQType::Llmqtype60_75, [(q3.llmq_hash, q3)].into());
+        let target = MasternodeList::new(masternodes, quorums, block.hash, block.height, true);
+
+        let mut coinbase_transaction = fixtures::coinbase_transaction(6);
+        coinbase_transaction.merkle_root_mn_list = target.masternode_merkle_root.unwrap();
+        coinbase_transaction.merkle_root_llmq_list = target.llmq_merkle_root;
+        let diff = MNListDiff::between(
+            &base,
+            &target,
+            coinbase_transaction.clone(),
+            1,
+            vec![UInt256([6u8; 32])],
+            vec![0x01],
+        );
+        assert_eq!(diff.base_block_hash, base.block_hash);
+        assert_eq!(diff.deleted_masternode_hashes, vec![deleted]);
+        assert_eq!(diff.added_or_modified_masternodes.len(), 2);
+        assert_eq!(diff.deleted_quorums[&LLMQType::Llmqtype50_60], vec![q1.llmq_hash]);
+        assert_eq!(diff.added_quorums.len(), 1);
+
+        // also when the diff went through the wire format, which doesn't carry the history
+        // of the entries: it's rebuilt from the base list
+        let data = diff.to_data();
+        let parsed = MNListDiff::new(&data, &mut 0, |hash| {
+            if hash == target.block_hash { 6 } else { 5 }
+        })
+        .expect("diff should be parsed");
+        for diff in [diff, parsed] {
+            let list = base.apply_diff(&diff).expect("diff should be applied");
+            assert_eq!(list.block_hash, target.block_hash);
+            assert_eq!(list.known_height, target.known_height);
+            assert_eq!(list.masternodes, target.masternodes);
+            assert_eq!(list.quorums, target.quorums);
+            assert_eq!(list, target);
+        }
+
+        // from the empty list, it's a full list diff which applies to any list
+        let diff = MNListDiff::between(
+            &MasternodeList::default(),
+            &target,
+            coinbase_transaction,
+            1,
+            vec![UInt256([6u8; 32])],
+            vec![0x01],
+        );
+        assert_eq!(diff.base_block_hash, UInt256::MIN);
+        assert!(diff.deleted_masternode_hashes.is_empty());
+        assert!(diff.deleted_quorums.is_empty());
+        assert_eq!(diff.added_or_modified_masternodes, target.masternodes);
+        assert_eq!(diff.added_quorums, target.quorums);
+        for list in [MasternodeList::default(), base] {
+            assert_eq!(list.apply_diff(&diff), Ok(target.clone()));
+        }
+    }
 }
@@ -5,6 +5,6 @@ pub mod snapshot;
 
 pub use self::llmq_typed_hash::LLMQIndexedHash;
 pub use self::llmq_typed_hash::LLMQTypedHash;
-pub use self::mn_list_diff::MNListDiff;
+pub use self::mn_list_diff::{MNListDiff, MerkleProofVerification};
 pub use self::rotation_info::LLMQRotationInfo;
-pub use self::snapshot::LLMQSnapshot;
+pub use self::snapshot::{LLMQSnapshot, SkipStrategyError};
@@ -1,5 +1,11 @@
-use crate::llmq::{LLMQSnapshot, MNListDiff};
-use crate::masternode::LLMQEntry;
+use crate::common::LLMQType;
+use crate::error::{read_field, Error};
+use crate::llmq::{LLMQSnapshot, MNListDiff, SkipStrategyError};
+use crate::masternode::{LLMQEntry, MasternodeEntry, MasternodeList};
+use dash_spv_primitives::consensus::encode::VarInt;
+use dash_spv_primitives::consensus::{Encodable, WriteExt};
+use dash_spv_primitives::crypto::UInt256;
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Debug)]
 pub struct LLMQRotationInfo {
@@ -18,3 +24,394 @@ pub struct LLMQRotationInfo {
     pub quorum_snapshot_list: Vec<LLMQSnapshot>,
     pub mn_list_diff_list: Vec<MNListDiff>,
 }
+
+impl LLMQRotationInfo {
+    /// Decodes `qrinfo` message payload whose diffs have no version fields
+    pub fn new<F: Fn(UInt256) -> u32>(
+        message: &[u8],
+        offset: &mut usize,
+        block_height_lookup: F,
+    ) -> Result<Self, Error> {
+        Self::new_with_protocol_version(message, offset, 0, block_height_lookup)
+    }
+
+    /// Decodes `qrinfo` message payload sent with the given protocol version
+    pub fn new_with_protocol_version<F: Fn(UInt256) -> u32>(
+        message: &[u8],
+        offset: &mut usize,
+        protocol_version: u32,
+        block_height_lookup: F,
+    ) -> Result<Self, Error> {
+        let read_diff = |offset: &mut usize| {
+            MNListDiff::new_with_protocol_version(
+                message,
+                offset,
+                protocol_version,
+                &block_height_lookup,
+            )
+        };
+        let snapshot_at_h_c = LLMQSnapshot::decode(message, offset)?;
+        let snapshot_at_h_2c = LLMQSnapshot::decode(message, offset)?;
+        let snapshot_at_h_3c = LLMQSnapshot::decode(message, offset)?;
+        let mn_list_diff_tip = read_diff(offset)?;
+        let mn_list_diff_at_h = read_diff(offset)?;
+        let mn_list_diff_at_h_c = read_diff(offset)?;
+        let mn_list_diff_at_h_2c = read_diff(offset)?;
+        let mn_list_diff_at_h_3c = read_diff(offset)?;
+        let extra_share =
+            read_field::<u8>(message, offset, "LLMQRotationInfo", "extra_share")? != 0;
+        let (snapshot_at_h_4c, mn_list_diff_at_h_4c) = if extra_share {
+            (
+                Some(LLMQSnapshot::decode(message, offset)?),
+                Some(read_diff(offset)?),
+            )
+        } else {
+            (None, None)
+        };
+        let last_quorum_per_index_count =
+            read_field::<VarInt>(message, offset, "LLMQRotationInfo", "last_quorum_per_index_count")?.0;
+        let mut last_quorum_per_index: Vec<LLMQEntry> = Vec::new();
+        for _i in 0..last_quorum_per_index_count {
+            last_quorum_per_index.push(LLMQEntry::decode(message, offset)?);
+        }
+        let quorum_snapshot_list_count =
+            read_field::<VarInt>(message, offset, "LLMQRotationInfo", "quorum_snapshot_list_count")?.0;
+        let mut quorum_snapshot_list: Vec<LLMQSnapshot> = Vec::new();
+        for _i in 0..quorum_snapshot_list_count {
+            quorum_snapshot_list.push(LLMQSnapshot::decode(message, offset)?);
+        }
+        let mn_list_diff_list_count =
+            read_field::<VarInt>(message, offset, "LLMQRotationInfo", "mn_list_diff_list_count")?.0;
+        let mut mn_list_diff_list: Vec<MNListDiff> = Vec::new();
+        for _i in 0..mn_list_diff_list_count {
+            mn_list_diff_list.push(read_diff(offset)?);
+        }
+        Ok(Self {
+            snapshot_at_h_c,
+            snapshot_at_h_2c,
+            snapshot_at_h_3c,
+            snapshot_at_h_4c,
+            mn_list_diff_tip,
+            mn_list_diff_at_h,
+            mn_list_diff_at_h_c,
+            mn_list_diff_at_h_2c,
+            mn_list_diff_at_h_3c,
+            mn_list_diff_at_h_4c,
+            extra_share,
+            last_quorum_per_index,
+            quorum_snapshot_list,
+            mn_list_diff_list,
+        })
+    }
+
+    /// Encodes the rotation info in `qrinfo` wire format, with diffs without version fields
+    pub fn to_data(&self) -> Vec<u8> {
+        self.to_data_with_protocol_version(0)
+    }
+
+    /// Encodes the rotation info in `qrinfo` wire format for the given protocol version
+    pub fn to_data_with_protocol_version(&self, protocol_version: u32) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let offset: &mut usize = &mut 0;
+        for snapshot in [
+            &self.snapshot_at_h_c,
+            &self.snapshot_at_h_2c,
+            &self.snapshot_at_h_3c,
+        ] {
+            let data = snapshot.to_data();
+            buffer.emit_slice(&data).unwrap();
+            *offset += data.len();
+        }
+        for diff in [
+            &self.mn_list_diff_tip,
+            &self.mn_list_diff_at_h,
+            &self.mn_list_diff_at_h_c,
+            &self.mn_list_diff_at_h_2c,
+            &self.mn_list_diff_at_h_3c,
+        ] {
+            let data = diff.to_data_with_protocol_version(protocol_version);
+            buffer.emit_slice(&data).unwrap();
+            *offset += data.len();
+        }
+        *offset += (self.extra_share as u8)
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        if self.extra_share {
+            if let Some(snapshot) = &self.snapshot_at_h_4c {
+                let data = snapshot.to_data();
+                buffer.emit_slice(&data).unwrap();
+                *offset += data.len();
+            }
+            if let Some(diff) = &self.mn_list_diff_at_h_4c {
+                let data = diff.to_data_with_protocol_version(protocol_version);
+                buffer.emit_slice(&data).unwrap();
+                *offset += data.len();
+            }
+        }
+        *offset += VarInt(self.last_quorum_per_index.len() as u64)
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        for entry in &self.last_quorum_per_index {
+            let data = entry.to_data();
+            buffer.emit_slice(&data).unwrap();
+            *offset += data.len();
+        }
+        *offset += VarInt(self.quorum_snapshot_list.len() as u64)
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        for snapshot in &self.quorum_snapshot_list {
+            let data = snapshot.to_data();
+            buffer.emit_slice(&data).unwrap();
+            *offset += data.len();
+        }
+        *offset += VarInt(self.mn_list_diff_list.len() as u64)
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        for diff in &self.mn_list_diff_list {
+            let data = diff.to_data_with_protocol_version(protocol_version);
+            buffer.emit_slice(&data).unwrap();
+            *offset += data.len();
+        }
+        buffer
+    }
+
+    /// Members of every rotated quorum of the cycle at h, indexed by quorum index.
+    /// Each list must be the masternode list at the work block (cycle base - 8) of its cycle,
+    /// i.e. the list obtained by applying mn_list_diff_at_h, mn_list_diff_at_h_c and so on
+    pub fn quorum_members(
+        &self,
+        llmq_type: LLMQType,
+        list_at_h: &MasternodeList,
+        list_at_h_c: &MasternodeList,
+        list_at_h_2c: &MasternodeList,
+        list_at_h_3c: &MasternodeList,
+    ) -> Result<Vec<Vec<MasternodeEntry>>, SkipStrategyError> {
+        let quorum_count = llmq_type.active_quorum_count() as usize;
+        let quarter_at_h_c =
+            Self::quarter_members_by_snapshot(llmq_type, list_at_h_c, &self.snapshot_at_h_c)?;
+        let quarter_at_h_2c =
+            Self::quarter_members_by_snapshot(llmq_type, list_at_h_2c, &self.snapshot_at_h_2c)?;
+        let quarter_at_h_3c =
+            Self::quarter_members_by_snapshot(llmq_type, list_at_h_3c, &self.snapshot_at_h_3c)?;
+        let new_quarter = Self::new_quarter_members(
+            llmq_type,
+            list_at_h,
+            [&quarter_at_h_c, &quarter_at_h_2c, &quarter_at_h_3c],
+        );
+        Ok((0..quorum_count)
+            .map(|i| {
+                [&quarter_at_h_3c, &quarter_at_h_2c, &quarter_at_h_c, &new_quarter]
+                    .iter()
+                    .filter_map(|quarters| quarters.get(i))
+                    .flatten()
+                    .cloned()
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Restores the quarters a past cycle has built from its snapshot
+    /// (GetQuorumQuarterMembersBySnapshot)
+    pub fn quarter_members_by_snapshot(
+        llmq_type: LLMQType,
+        masternode_list: &MasternodeList,
+        snapshot: &LLMQSnapshot,
+    ) -> Result<Vec<Vec<MasternodeEntry>>, SkipStrategyError> {
+        let params = llmq_type.params();
+        let quorum_count = params.signing_active_quorum_count as usize;
+        let quarter_size = (params.size / 4) as usize;
+        let modifier = MasternodeList::quorum_modifier(llmq_type, masternode_list.block_hash);
+        let sorted_masternodes = MasternodeList::sort_masternodes_by_score(
+            masternode_list.masternodes.values().cloned().collect(),
+            modifier,
+            masternode_list.known_height,
+        );
+        // both parts keep the score order, so the list begins with unused masternodes
+        // followed by the ones already used at h
+        let (used, mut sorted_combined_masternodes): (Vec<_>, Vec<_>) = sorted_masternodes
+            .into_iter()
+            .enumerate()
+            .partition(|(i, _)| {
+                matches!(snapshot.member_list.get(i / 8), Some(bits) if bits & (1 << (i % 8)) != 0)
+            });
+        sorted_combined_masternodes.extend(used);
+        let sorted_combined_masternodes: Vec<MasternodeEntry> = sorted_combined_masternodes
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect();
+        if sorted_combined_masternodes.is_empty() {
+            return Ok(vec![Vec::new(); quorum_count]);
+        }
+        snapshot.apply_skip_strategy(sorted_combined_masternodes, quorum_count, quarter_size)
+    }
+
+    /// Builds the new quarter of the cycle so that no quorum index gets a masternode it already
+    /// had in the previous quarters (BuildNewQuorumQuarterMembers).
+    /// previous_quarters are the quarters at h-c, h-2c and h-3c
+    pub fn new_quarter_members(
+        llmq_type: LLMQType,
+        masternode_list: &MasternodeList,
+        previous_quarters: [&Vec<Vec<MasternodeEntry>>; 3],
+    ) -> Vec<Vec<MasternodeEntry>> {
+        let params = llmq_type.params();
+        let quorum_count = params.signing_active_quorum_count as usize;
+        let quarter_size = (params.size / 4) as usize;
+        let mut quarter_quorum_members = vec![Vec::<MasternodeEntry>::new(); quorum_count];
+        let valid_masternodes: BTreeMap<UInt256, &MasternodeEntry> = masternode_list
+            .masternodes
+            .values()
+            .filter(|entry| entry.is_valid)
+            .map(|entry| (entry.provider_registration_transaction_hash, entry))
+            .collect();
+        if valid_masternodes.len() < quarter_size {
+            return quarter_quorum_members;
+        }
+        let mut used_masternodes: BTreeMap<UInt256, MasternodeEntry> = BTreeMap::new();
+        let mut used_masternodes_indexed = vec![BTreeSet::<UInt256>::new(); quorum_count];
+        for (i, used_indexed) in used_masternodes_indexed.iter_mut().enumerate() {
+            for quarters in previous_quarters {
+                for entry in quarters.get(i).into_iter().flatten() {
+                    let hash = entry.provider_registration_transaction_hash;
+                    // removed and banned masternodes don't count as used
+                    if !valid_masternodes.contains_key(&hash) {
+                        continue;
+                    }
+                    used_masternodes.entry(hash).or_insert_with(|| entry.clone());
+                    used_indexed.insert(hash);
+                }
+            }
+        }
+        let modifier = MasternodeList::quorum_modifier(llmq_type, masternode_list.block_hash);
+        let unused_masternodes = valid_masternodes
+            .iter()
+            .filter(|(hash, _)| !used_masternodes.contains_key(*hash))
+            .map(|(_, entry)| (*entry).clone())
+            .collect();
+        let mut sorted_combined_masternodes = MasternodeList::sort_masternodes_by_score(
+            unused_masternodes,
+            modifier,
+            masternode_list.known_height,
+        );
+        sorted_combined_masternodes.extend(MasternodeList::sort_masternodes_by_score(
+            used_masternodes.into_values().collect(),
+            modifier,
+            masternode_list.known_height,
+        ));
+        let combined_count = sorted_combined_masternodes.len();
+        if combined_count == 0 {
+            return quarter_quorum_members;
+        }
+        let mut idx = 0;
+        for (quarter, used_indexed) in quarter_quorum_members
+            .iter_mut()
+            .zip(used_masternodes_indexed.iter_mut())
+        {
+            let used_count = used_indexed.len();
+            let initial_loop_idx = idx;
+            let mut updated = false;
+            while quarter.len() < quarter_size && used_count + quarter.len() < combined_count {
+                let entry = &sorted_combined_masternodes[idx];
+                if used_indexed.insert(entry.provider_registration_transaction_hash) {
+                    quarter.push(entry.clone());
+                    updated = true;
+                }
+                idx += 1;
+                if idx == combined_count {
+                    idx = 0;
+                }
+                if idx == initial_loop_idx {
+                    if !updated {
+                        // a full pass didn't add anyone: there are not enough masternodes
+                        return vec![Vec::new(); quorum_count];
+                    }
+                    updated = false;
+                }
+            }
+        }
+        quarter_quorum_members
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{LLMQSnapshotSkipMode, LLMQType};
+    use crate::fixtures;
+    use crate::llmq::{LLMQRotationInfo, LLMQSnapshot};
+    use dash_spv_primitives::crypto::UInt256;
+
+    fn rotation_info(extra_share: bool) -> LLMQRotationInfo {
+        let diff = |seed: u8| fixtures::mn_list_diff(UInt256([0u8; 32]), UInt256([seed; 32]), seed);
+        LLMQRotationInfo {
+            snapshot_at_h_c: fixtures::llmq_snapshot(1),
+            snapshot_at_h_2c: fixtures::llmq_snapshot(2),
+            snapshot_at_h_3c: fixtures::llmq_snapshot(3),
+            snapshot_at_h_4c: if extra_share { Some(fixtures::llmq_snapshot(4)) } else { None },
+            mn_list_diff_tip: diff(10),
+            mn_list_diff_at_h: diff(20),
+            mn_list_diff_at_h_c: diff(30),
+            mn_list_diff_at_h_2c: diff(40),
+            mn_list_diff_at_h_3c: diff(50),
+            mn_list_diff_at_h_4c: if extra_share { Some(diff(60)) } else { None },
+            extra_share,
+            last_quorum_per_index: vec![
+                fixtures::llmq_entry(LLMQType::LlmqtypeTestDIP0024, 70),
+                fixtures::llmq_entry(LLMQType::LlmqtypeTestDIP0024, 71),
+            ],
+            quorum_snapshot_list: vec![fixtures::llmq_snapshot(5)],
+            mn_list_diff_list: vec![diff(80)],
+        }
+    }
+
+    #[test]
+    fn rotation_info_round_trip() {
+        let info = rotation_info(false);
+        let data = info.to_data();
+        let parsed = LLMQRotationInfo::new(&data, &mut 0, |_| 1).expect("qrinfo should be parsed");
+        assert!(!parsed.extra_share);
+        assert!(parsed.snapshot_at_h_4c.is_none());
+        assert_eq!(parsed.last_quorum_per_index.len(), 2);
+        assert_eq!(parsed.to_data(), data);
+    }
+
+    #[test]
+    fn rotation_info_with_extra_share_round_trip() {
+        let info = rotation_info(true);
+        let data = info.to_data();
+        let offset = &mut 0;
+        let parsed = LLMQRotationInfo::new(&data, offset, |_| 1).expect("qrinfo should be parsed");
+        assert_eq!(*offset, data.len());
+        assert!(parsed.extra_share);
+        assert!(parsed.mn_list_diff_at_h_4c.is_some());
+        assert_eq!(parsed.to_data(), data);
+    }
+
+    #[test]
+    fn rotated_quorum_members() {
+        let llmq_type = LLMQType::LlmqtypeTestDIP0024;
+        let seeds: Vec<u8> = (1..=8).map(|i| i * 10).collect();
+        let list = |height: u32| {
+            fixtures::masternode_list(UInt256([height as u8; 32]), height, &seeds)
+        };
+        let mut info = rotation_info(false);
+        for snapshot in [
+            &mut info.snapshot_at_h_c,
+            &mut info.snapshot_at_h_2c,
+            &mut info.snapshot_at_h_3c,
+        ] {
+            *snapshot = LLMQSnapshot::new(vec![0], vec![], LLMQSnapshotSkipMode::NoSkipping);
+        }
+        let members = info
+            .quorum_members(llmq_type, &list(40), &list(30), &list(20), &list(10))
+            .expect("snapshots should match the lists");
+        assert_eq!(members.len(), llmq_type.active_quorum_count() as usize);
+        for quorum in &members {
+            assert_eq!(quorum.len(), llmq_type.size() as usize);
+            // the new quarter never reuses a masternode of the previous quarters of its index
+            let new_member = quorum.last().unwrap();
+            assert!(quorum[..3]
+                .iter()
+                .all(|entry| entry.provider_registration_transaction_hash
+                    != new_member.provider_registration_transaction_hash));
+        }
+    }
+}
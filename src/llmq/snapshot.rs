@@ -1,17 +1,33 @@
 use crate::common::LLMQSnapshotSkipMode;
+use crate::error::{read_bytes, read_field, Error};
 use crate::masternode::MasternodeEntry;
-use byte::ctx::{Bytes, Endian};
-use byte::{BytesExt, TryRead, LE};
+use byte::ctx::Endian;
+use byte::{BytesExt, TryRead, TryWrite, LE};
 use dash_spv_primitives::consensus::encode::VarInt;
+use dash_spv_primitives::consensus::{Encodable, WriteExt};
 use dash_spv_primitives::crypto::byte_util::BytesDecodable;
+use dash_spv_primitives::crypto::UInt256;
 use dash_spv_primitives::hashes::hex::ToHex;
 use dash_spv_primitives::impl_bytes_decodable;
+use std::collections::BTreeSet;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SkipStrategyError {
+    // There are no masternodes to fill the quarters with
+    EmptyMasternodeList,
+    // The skip list must be empty in this mode
+    UnexpectedSkipList,
+    // The skip list points past the end of the masternode list
+    SkipIndexOutOfRange,
+}
 
 #[derive(Clone)]
 pub struct LLMQSnapshot {
     // The bitset of nodes already in quarters at the start of cycle at height n
     // (masternodeListSize + 7)/8
     pub member_list: Vec<u8>,
+    // Number of bits in member_list (masternodeListSize)
+    pub member_list_length: usize,
     // Skiplist at height n
     pub skip_list: Vec<i32>,
     //  Mode of the skip list
@@ -21,6 +37,7 @@ impl Default for LLMQSnapshot {
     fn default() -> Self {
         Self {
             member_list: vec![],
+            member_list_length: 0,
             skip_list: vec![],
             skip_list_mode: LLMQSnapshotSkipMode::NoSkipping,
         }
@@ -31,6 +48,7 @@ impl<'a> std::fmt::Debug for LLMQSnapshot {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("LLMQSnapshot")
             .field("member_list", &self.member_list.to_hex())
+            .field("member_list_length", &self.member_list_length)
             .field("skip_list", &self.skip_list.iter())
             .field("skip_list_mode", &self.skip_list_mode)
             .finish()
@@ -39,108 +57,410 @@ impl<'a> std::fmt::Debug for LLMQSnapshot {
 impl<'a> TryRead<'a, Endian> for LLMQSnapshot {
     fn try_read(bytes: &'a [u8], _endian: Endian) -> byte::Result<(Self, usize)> {
         let offset = &mut 0;
-        let skip_list_mode = bytes.read_with::<LLMQSnapshotSkipMode>(offset, LE)?;
-        let member_list_length = bytes.read_with::<VarInt>(offset, LE)?.0 as usize;
-        let member_list: &[u8] =
-            bytes.read_with(offset, Bytes::Len((member_list_length + 7) / 8))?;
-        let skip_list_length = bytes.read_with::<VarInt>(offset, LE)?.0 as usize;
-        let mut skip_list = Vec::with_capacity(skip_list_length);
-        for _i in 0..skip_list_length {
-            skip_list.push(bytes.read_with::<i32>(offset, LE)?);
+        let snapshot = Self::decode(bytes, offset)?;
+        Ok((snapshot, *offset))
+    }
+}
+
+impl TryWrite<Endian> for LLMQSnapshot {
+    fn try_write(self, bytes: &mut [u8], _endian: Endian) -> byte::Result<usize> {
+        let data = self.to_data();
+        if bytes.len() < data.len() {
+            return Err(byte::Error::Incomplete);
         }
-        Ok((
-            Self {
-                member_list: member_list.to_vec(),
-                skip_list,
-                skip_list_mode,
-            },
-            *offset,
-        ))
+        bytes[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
     }
 }
 
 impl LLMQSnapshot {
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        let skip_list_mode_offset = *offset;
+        let skip_list_mode_value =
+            read_field::<u32>(bytes, offset, "LLMQSnapshot", "skip_list_mode")?;
+        let skip_list_mode = match skip_list_mode_value {
+            0 => LLMQSnapshotSkipMode::NoSkipping,
+            1 => LLMQSnapshotSkipMode::SkipFirst,
+            2 => LLMQSnapshotSkipMode::SkipExcept,
+            3 => LLMQSnapshotSkipMode::SkipAll,
+            _ => {
+                return Err(Error::BadInput {
+                    structure: "LLMQSnapshot",
+                    field: "skip_list_mode",
+                    offset: skip_list_mode_offset,
+                    reason: "unknown skip list mode",
+                })
+            }
+        };
+        let member_list_length =
+            read_field::<VarInt>(bytes, offset, "LLMQSnapshot", "member_list_length")?.0 as usize;
+        let member_list = read_bytes(
+            bytes,
+            offset,
+            member_list_length.div_ceil(8),
+            "LLMQSnapshot",
+            "member_list",
+        )?;
+        let skip_list_length =
+            read_field::<VarInt>(bytes, offset, "LLMQSnapshot", "skip_list_length")?.0;
+        let mut skip_list = Vec::new();
+        for _i in 0..skip_list_length {
+            skip_list.push(read_field::<i32>(bytes, offset, "LLMQSnapshot", "skip_list")?);
+        }
+        Ok(Self {
+            member_list: member_list.to_vec(),
+            member_list_length,
+            skip_list,
+            skip_list_mode,
+        })
+    }
+
 
     pub fn new(member_list: Vec<u8>, skip_list: Vec<i32>, skip_list_mode: LLMQSnapshotSkipMode) -> Self {
         LLMQSnapshot {
+            member_list_length: member_list.len() * 8,
             member_list,
             skip_list,
             skip_list_mode
         }
     }
 
+    /// Snapshot of a cycle (BuildQuorumSnapshot).
+    /// sorted_masternodes are the valid masternodes sorted by score for the cycle, used_masternodes
+    /// the pro_reg_tx hashes already in quarters and skipped_indices the positions skipped while
+    /// building the new quarters in the combined list (unused masternodes first, then used ones),
+    /// in the order they were skipped
+    pub fn build(
+        sorted_masternodes: &[MasternodeEntry],
+        used_masternodes: &BTreeSet<UInt256>,
+        skipped_indices: &[usize],
+    ) -> Self {
+        let mut member_list = vec![0u8; sorted_masternodes.len().div_ceil(8)];
+        sorted_masternodes
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                used_masternodes.contains(&entry.provider_registration_transaction_hash)
+            })
+            .for_each(|(i, _)| member_list[i / 8] |= 1 << (i % 8));
+        // Dash Core only ever builds these two modes
+        let (skip_list_mode, skip_list) = if skipped_indices.is_empty() {
+            (LLMQSnapshotSkipMode::NoSkipping, vec![])
+        } else {
+            // positions are relative to the first skipped one, which Dash Core doesn't consider
+            // set while it is 0. They're negative for skips after the index wrapped around
+            let mut first_entry_index = 0i32;
+            let skip_list = skipped_indices
+                .iter()
+                .map(|idx| {
+                    let idx = *idx as i32;
+                    if first_entry_index == 0 {
+                        first_entry_index = idx;
+                        idx
+                    } else {
+                        idx - first_entry_index
+                    }
+                })
+                .collect();
+            (LLMQSnapshotSkipMode::SkipFirst, skip_list)
+        };
+        Self {
+            member_list,
+            member_list_length: sorted_masternodes.len(),
+            skip_list,
+            skip_list_mode,
+        }
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let offset: &mut usize = &mut 0;
+        *offset += (self.skip_list_mode as u32)
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        *offset += VarInt(self.member_list_length as u64)
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        buffer.emit_slice(&self.member_list).unwrap();
+        *offset += self.member_list.len();
+        *offset += VarInt(self.skip_list.len() as u64)
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        for skipped in &self.skip_list {
+            *offset += skipped.consensus_encode(&mut buffer).unwrap();
+        }
+        buffer
+    }
+
     pub fn length(&self) -> usize {
-        self.member_list.len() + 1 + 2 + self.skip_list.len() * 2
+        4 + VarInt(self.member_list_length as u64).len()
+            + self.member_list.len()
+            + VarInt(self.skip_list.len() as u64).len()
+            + self.skip_list.len() * 4
     }
 
+    /// Distributes the combined list (unused masternodes first, then used ones) between
+    /// quorum_num quarters of quarter_size members according to the skip list
+    /// (GetQuorumQuarterMembersBySnapshot)
     pub fn apply_skip_strategy(
         &self,
         sorted_combined_mns_list: Vec<MasternodeEntry>,
         quorum_num: usize,
         quarter_size: usize,
-    ) -> Vec<Vec<MasternodeEntry>> {
-        let mut quarter_quorum_members = Vec::<Vec<MasternodeEntry>>::with_capacity(quorum_num);
+    ) -> Result<Vec<Vec<MasternodeEntry>>, SkipStrategyError> {
+        let list_size = sorted_combined_mns_list.len();
         match self.skip_list_mode {
             LLMQSnapshotSkipMode::NoSkipping => {
-                let mut iter = sorted_combined_mns_list.iter();
-                (0..quorum_num).for_each(|_i| {
-                    let mut quarter = Vec::<MasternodeEntry>::new();
-                    while quarter.len() < quarter_size {
-                        if let Some(node) = iter.next() {
-                            quarter.push(node.clone());
-                        } else {
-                            iter = sorted_combined_mns_list.iter();
-                        }
-                    }
-                    quarter_quorum_members.push(quarter);
-                });
+                if !self.skip_list.is_empty() {
+                    return Err(SkipStrategyError::UnexpectedSkipList);
+                }
+                Self::fill_quarters(
+                    &sorted_combined_mns_list,
+                    (0..list_size).cycle(),
+                    quorum_num,
+                    quarter_size,
+                )
             }
             LLMQSnapshotSkipMode::SkipFirst => {
-                let mut first_entry_index = 0;
-                let mut processed_skip_list = Vec::<i32>::new();
-                self.skip_list.iter().for_each(|s| {
-                    let index = first_entry_index + s;
-                    if first_entry_index == 0 {
-                        first_entry_index = *s;
+                // entries after the first one are relative to it; as in Dash Core a first
+                // entry of 0 doesn't count, so the next one is absolute
+                let mut first_entry_index: i64 = 0;
+                let mut skipped = Vec::<usize>::with_capacity(self.skip_list.len());
+                for s in &self.skip_list {
+                    let index = if first_entry_index == 0 {
+                        first_entry_index = *s as i64;
+                        *s as i64
+                    } else {
+                        first_entry_index + *s as i64
+                    };
+                    if index < 0 || index >= list_size as i64 {
+                        return Err(SkipStrategyError::SkipIndexOutOfRange);
                     }
-                    processed_skip_list.push(index)
-                });
-                let mut index: usize = 0;
-                let mut idxk: usize = 0;
-                (0..quorum_num).for_each(|_i| {
-                    let mut quarter = Vec::<MasternodeEntry>::new();
-                    while quarter.len() < quarter_size {
-                        if let Some(_skipped) = processed_skip_list.get(idxk) {
-                            idxk += 1;
-                        } else if let Some(node) = sorted_combined_mns_list.get(index) {
-                            quarter.push(node.clone());
-                            index += 1;
-                            if index == sorted_combined_mns_list.len() {
-                                index = 0;
-                            }
-                        }
+                    skipped.push(index as usize);
+                }
+                // the index cycles through the list and each skip entry is consumed, in order,
+                // the next time the index reaches it, so skips may follow a wraparound
+                let mut skipped = skipped.into_iter().peekable();
+                let indices = (0..list_size).cycle().filter(move |index| {
+                    let is_skipped = skipped.peek() == Some(index);
+                    if is_skipped {
+                        skipped.next();
                     }
-                    quarter_quorum_members.push(quarter);
+                    !is_skipped
                 });
+                Self::fill_quarters(&sorted_combined_mns_list, indices, quorum_num, quarter_size)
             }
             LLMQSnapshotSkipMode::SkipExcept => {
-                (0..quorum_num).for_each(|_i| {
-                    let mut quarter = Vec::<MasternodeEntry>::new();
-                    self.skip_list.iter().for_each(|unskipped| {
-                        if let Some(node) = sorted_combined_mns_list.get(*unskipped as usize) {
-                            if quarter.len() < quarter_size {
-                                quarter.push(node.clone());
-                            }
-                        }
-                    });
-                    quarter_quorum_members.push(quarter);
-                });
+                // Dash Core never builds this mode and doesn't restore members from it either
+                Ok(vec![Vec::new(); quorum_num])
             }
             LLMQSnapshotSkipMode::SkipAll => {
-                // TODO: do we need to impl smth in this strategy ?
+                if !self.skip_list.is_empty() {
+                    return Err(SkipStrategyError::UnexpectedSkipList);
+                }
+                // DKG sessions were not attempted, so no quarter has members
+                Ok(vec![Vec::new(); quorum_num])
             }
         }
-        quarter_quorum_members
+    }
+
+    // Takes quarter_size entries per quarter at the positions yielded by indices
+    fn fill_quarters(
+        sorted_combined_mns_list: &[MasternodeEntry],
+        mut indices: impl Iterator<Item = usize>,
+        quorum_num: usize,
+        quarter_size: usize,
+    ) -> Result<Vec<Vec<MasternodeEntry>>, SkipStrategyError> {
+        if quorum_num == 0 || quarter_size == 0 {
+            return Ok(vec![Vec::new(); quorum_num]);
+        }
+        if sorted_combined_mns_list.is_empty() {
+            return Err(SkipStrategyError::EmptyMasternodeList);
+        }
+        Ok((0..quorum_num)
+            .map(|_i| {
+                (&mut indices)
+                    .take(quarter_size)
+                    .map(|index| sorted_combined_mns_list[index].clone())
+                    .collect()
+            })
+            .collect())
     }
 }
 impl_bytes_decodable!(LLMQSnapshot);
+
+#[cfg(test)]
+mod tests {
+    use crate::common::LLMQSnapshotSkipMode;
+    use crate::fixtures;
+    use crate::llmq::{LLMQSnapshot, SkipStrategyError};
+    use crate::masternode::MasternodeEntry;
+    use std::collections::BTreeSet;
+
+    // Synthetic lists: entry i of the list is made from seed i, so quarters compare as seeds
+    fn apply(
+        mode: LLMQSnapshotSkipMode,
+        skip_list: Vec<i32>,
+        list_size: u8,
+        quorum_num: usize,
+        quarter_size: usize,
+    ) -> Result<Vec<Vec<u8>>, SkipStrategyError> {
+        let list = (0..list_size).map(fixtures::masternode_entry).collect();
+        LLMQSnapshot::new(vec![], skip_list, mode)
+            .apply_skip_strategy(list, quorum_num, quarter_size)
+            .map(|quarters| {
+                quarters
+                    .iter()
+                    .map(|quarter| {
+                        quarter
+                            .iter()
+                            .map(|entry| entry.provider_registration_transaction_hash.0[0])
+                            .collect()
+                    })
+                    .collect()
+            })
+    }
+
+    #[test]
+    fn skip_strategies() {
+        use LLMQSnapshotSkipMode::*;
+        assert_eq!(
+            apply(NoSkipping, vec![], 5, 2, 3),
+            Ok(vec![vec![0, 1, 2], vec![3, 4, 0]])
+        );
+        // skips 0, 3 and 5 (0 doesn't count as the first entry), then starts over
+        assert_eq!(
+            apply(SkipFirst, vec![0, 3, 2], 6, 2, 3),
+            Ok(vec![vec![1, 2, 4], vec![0, 1, 2]])
+        );
+        assert_eq!(
+            apply(SkipFirst, vec![1, 2], 5, 2, 2),
+            Ok(vec![vec![0, 2], vec![4, 0]])
+        );
+        // skips 3, then 1 once the index wrapped around
+        assert_eq!(
+            apply(SkipFirst, vec![3, -2], 5, 2, 3),
+            Ok(vec![vec![0, 1, 2], vec![4, 0, 2]])
+        );
+        // skips 2 in two consecutive rounds
+        assert_eq!(
+            apply(SkipFirst, vec![2, 0], 4, 2, 3),
+            Ok(vec![vec![0, 1, 3], vec![0, 1, 3]])
+        );
+        // every entry is skipped once, then taken
+        assert_eq!(apply(SkipFirst, vec![0, 1], 2, 2, 2), Ok(vec![vec![0, 1], vec![0, 1]]));
+        // as in Dash Core, no members are restored from a list of kept entries
+        assert_eq!(apply(SkipExcept, vec![1, 3], 5, 2, 3), Ok(vec![vec![], vec![]]));
+        assert_eq!(apply(SkipExcept, vec![], 5, 2, 3), Ok(vec![vec![], vec![]]));
+        assert_eq!(apply(SkipAll, vec![], 5, 2, 3), Ok(vec![vec![], vec![]]));
+    }
+
+    #[test]
+    fn inconsistent_skip_lists_are_rejected() {
+        use LLMQSnapshotSkipMode::*;
+        assert_eq!(
+            apply(SkipFirst, vec![2, 3], 4, 1, 1),
+            Err(SkipStrategyError::SkipIndexOutOfRange)
+        );
+        assert_eq!(
+            apply(SkipFirst, vec![2, -3], 4, 1, 1),
+            Err(SkipStrategyError::SkipIndexOutOfRange)
+        );
+        assert_eq!(apply(NoSkipping, vec![1], 4, 1, 1), Err(SkipStrategyError::UnexpectedSkipList));
+        assert_eq!(apply(NoSkipping, vec![], 0, 1, 1), Err(SkipStrategyError::EmptyMasternodeList));
+    }
+
+    #[test]
+    fn built_snapshot_restores_quarters() {
+        let list: Vec<MasternodeEntry> = (0..8).map(fixtures::masternode_entry).collect();
+        let seeds = |quarters: Vec<Vec<MasternodeEntry>>| -> Vec<Vec<u8>> {
+            quarters
+                .iter()
+                .map(|quarter| {
+                    quarter
+                        .iter()
+                        .map(|entry| entry.provider_registration_transaction_hash.0[0])
+                        .collect()
+                })
+                .collect()
+        };
+        let snapshot = LLMQSnapshot::build(&list, &BTreeSet::new(), &[2, 5]);
+        assert_eq!(snapshot.skip_list_mode, LLMQSnapshotSkipMode::SkipFirst);
+        let quarters = snapshot.apply_skip_strategy(list.clone(), 2, 2).unwrap();
+        assert_eq!(seeds(quarters), vec![vec![0, 1], vec![3, 4]]);
+        // 6 was skipped, then 1 after the builder wrapped around
+        let snapshot = LLMQSnapshot::build(&list, &BTreeSet::new(), &[6, 1]);
+        assert_eq!(snapshot.skip_list, vec![6, -5]);
+        let quarters = snapshot.apply_skip_strategy(list, 3, 3).unwrap();
+        assert_eq!(seeds(quarters), vec![vec![0, 1, 2], vec![3, 4, 5], vec![7, 0, 2]]);
+    }
+
+    #[test]
+    fn snapshot_builder_picks_skip_mode() {
+        let masternodes: Vec<MasternodeEntry> =
+            (1..=12).map(fixtures::masternode_entry).collect();
+        let used = [0, 9]
+            .iter()
+            .map(|i: &usize| masternodes[*i].provider_registration_transaction_hash)
+            .collect::<BTreeSet<_>>();
+        let snapshot = LLMQSnapshot::build(&masternodes, &used, &[]);
+        assert_eq!(snapshot.member_list, vec![0b0000_0001, 0b0000_0010]);
+        assert_eq!(snapshot.skip_list_mode, LLMQSnapshotSkipMode::NoSkipping);
+        assert!(snapshot.skip_list.is_empty());
+
+        let snapshot = LLMQSnapshot::build(&masternodes, &used, &[3, 5, 6]);
+        assert_eq!(snapshot.skip_list_mode, LLMQSnapshotSkipMode::SkipFirst);
+        assert_eq!(snapshot.skip_list, vec![3, 2, 3]);
+
+        // no other mode, even when nearly everything or everything is skipped
+        let snapshot = LLMQSnapshot::build(&masternodes, &used, &[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(snapshot.skip_list_mode, LLMQSnapshotSkipMode::SkipFirst);
+        assert_eq!(snapshot.skip_list, vec![1, 1, 2, 3, 4, 5, 6, 7, 8]);
+        let all: Vec<usize> = (0..masternodes.len()).collect();
+        let snapshot = LLMQSnapshot::build(&masternodes, &used, &all);
+        assert_eq!(snapshot.skip_list_mode, LLMQSnapshotSkipMode::SkipFirst);
+        // a first skip at 0 leaves the reference unset so the next one becomes it
+        assert_eq!(snapshot.skip_list, vec![0, 1, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn snapshot_skip_offsets_are_signed() {
+        let masternodes: Vec<MasternodeEntry> =
+            (1..=12).map(fixtures::masternode_entry).collect();
+        // the index wrapped around the combined list after the first skip
+        let snapshot = LLMQSnapshot::build(&masternodes, &BTreeSet::new(), &[10, 11, 1, 4]);
+        assert_eq!(snapshot.skip_list_mode, LLMQSnapshotSkipMode::SkipFirst);
+        assert_eq!(snapshot.skip_list, vec![10, 1, -9, -6]);
+        let data = snapshot.to_data();
+        let parsed = LLMQSnapshot::decode(&data, &mut 0).expect("snapshot should be parsed");
+        assert_eq!(parsed.skip_list, vec![10, 1, -9, -6]);
+    }
+
+    #[test]
+    fn huge_member_count_is_incomplete() {
+        let mut data = vec![0u8; 4];
+        data.extend([0xff; 9]);
+        let err = LLMQSnapshot::decode(&data, &mut 0).unwrap_err();
+        assert_eq!((err.field(), err.offset()), ("member_list", 13));
+    }
+
+    #[test]
+    fn snapshot_round_trip_keeps_member_count() {
+        let masternodes: Vec<MasternodeEntry> = (1..=11).map(fixtures::masternode_entry).collect();
+        let used = BTreeSet::from([masternodes[10].provider_registration_transaction_hash]);
+        let skipped: Vec<usize> = (0..300).collect();
+        let mut snapshot = LLMQSnapshot::build(&masternodes, &used, &skipped[..3]);
+        snapshot.skip_list = skipped.iter().map(|i| *i as i32).collect();
+        let data = snapshot.to_data();
+        assert_eq!(snapshot.length(), data.len());
+        let parsed = LLMQSnapshot::decode(&data, &mut 0).expect("snapshot should be parsed");
+        assert_eq!(parsed.member_list_length, 11);
+        assert_eq!(parsed.to_data(), data);
+        let mut written = vec![0u8; data.len()];
+        assert_eq!(
+            byte::TryWrite::try_write(snapshot, &mut written, byte::LE).unwrap(),
+            data.len()
+        );
+        assert_eq!(written, data);
+    }
+}
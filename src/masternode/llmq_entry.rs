@@ -1,6 +1,8 @@
 use crate::common::LLMQType;
-use byte::ctx::{Bytes, Endian};
-use byte::{BytesExt, TryRead, LE};
+use crate::error::{read_bytes, read_field, Error};
+use crate::masternode::MasternodeEntry;
+use byte::ctx::Endian;
+use byte::TryRead;
 use dash_spv_primitives::consensus::encode::VarInt;
 use dash_spv_primitives::consensus::{Encodable, WriteExt};
 use dash_spv_primitives::crypto::data_ops::Data;
@@ -58,66 +60,62 @@ impl std::fmt::Debug for LLMQEntry {
 impl<'a> TryRead<'a, Endian> for LLMQEntry {
     fn try_read(bytes: &'a [u8], _ctx: Endian) -> byte::Result<(Self, usize)> {
         let offset = &mut 0;
-        let version = bytes.read_with::<u16>(offset, LE)?;
-        let llmq_type = bytes.read_with::<LLMQType>(offset, LE)?;
-        let llmq_hash = bytes.read_with::<UInt256>(offset, LE)?;
+        let entry = Self::decode(bytes, offset)?;
+        Ok((entry, *offset))
+    }
+}
+
+impl LLMQEntry {
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        let version = read_field::<u16>(bytes, offset, "LLMQEntry", "version")?;
+        let llmq_type = read_field::<LLMQType>(bytes, offset, "LLMQEntry", "llmq_type")?;
+        let llmq_hash = read_field::<UInt256>(bytes, offset, "LLMQEntry", "llmq_hash")?;
         let index = if version >= LLMQ_INDEXED_VERSION {
-            Some(bytes.read_with::<u16>(offset, LE)?)
+            Some(read_field::<u16>(bytes, offset, "LLMQEntry", "index")?)
         } else {
             None
         };
-        let signers_count = bytes.read_with::<VarInt>(offset, LE)?;
-        let signers_buffer_length: usize = ((signers_count.0 as usize) + 7) / 8;
-        let signers_bitset = bytes.read_with(offset, Bytes::Len(signers_buffer_length))?;
-        let valid_members_count = bytes.read_with::<VarInt>(offset, LE)?;
-        let valid_members_count_buffer_length: usize = ((valid_members_count.0 as usize) + 7) / 8;
-        let valid_members_bitset =
-            bytes.read_with(offset, Bytes::Len(valid_members_count_buffer_length))?;
-        let public_key = bytes.read_with::<UInt384>(offset, LE)?;
-        let verification_vector_hash = bytes.read_with::<UInt256>(offset, LE)?;
-        let threshold_signature = bytes.read_with::<UInt768>(offset, LE)?;
-        let all_commitment_aggregated_signature = bytes.read_with::<UInt768>(offset, LE)?;
-        let q_data = Self::generate_data(
+        let signers_count = read_field::<VarInt>(bytes, offset, "LLMQEntry", "signers_count")?;
+        let signers_buffer_length: usize = (signers_count.0 as usize).div_ceil(8);
+        let signers_bitset =
+            read_bytes(bytes, offset, signers_buffer_length, "LLMQEntry", "signers_bitset")?;
+        let valid_members_count =
+            read_field::<VarInt>(bytes, offset, "LLMQEntry", "valid_members_count")?;
+        let valid_members_count_buffer_length: usize = (valid_members_count.0 as usize).div_ceil(8);
+        let valid_members_bitset = read_bytes(
+            bytes,
+            offset,
+            valid_members_count_buffer_length,
+            "LLMQEntry",
+            "valid_members_bitset",
+        )?;
+        let public_key = read_field::<UInt384>(bytes, offset, "LLMQEntry", "public_key")?;
+        let verification_vector_hash =
+            read_field::<UInt256>(bytes, offset, "LLMQEntry", "verification_vector_hash")?;
+        let threshold_signature =
+            read_field::<UInt768>(bytes, offset, "LLMQEntry", "threshold_signature")?;
+        let all_commitment_aggregated_signature = read_field::<UInt768>(
+            bytes,
+            offset,
+            "LLMQEntry",
+            "all_commitment_aggregated_signature",
+        )?;
+        Ok(Self::new(
             version,
             llmq_type,
             llmq_hash,
             index,
             signers_count,
-            signers_bitset,
             valid_members_count,
-            valid_members_bitset,
+            signers_bitset.to_vec(),
+            valid_members_bitset.to_vec(),
             public_key,
             verification_vector_hash,
             threshold_signature,
             all_commitment_aggregated_signature,
-        );
-        let entry_hash = UInt256(sha256d::Hash::hash(q_data.as_slice()).into_inner());
-
-        Ok((
-            LLMQEntry {
-                version,
-                llmq_hash,
-                index,
-                public_key,
-                threshold_signature,
-                verification_vector_hash,
-                all_commitment_aggregated_signature,
-                signers_count,
-                llmq_type,
-                valid_members_count,
-                signers_bitset: signers_bitset.to_vec(),
-                valid_members_bitset: valid_members_bitset.to_vec(),
-                entry_hash,
-                verified: false,
-                saved: false,
-                commitment_hash: None,
-            },
-            *offset,
         ))
     }
-}
 
-impl LLMQEntry {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         version: u16,
@@ -278,12 +276,45 @@ impl LLMQEntry {
         self.commitment_hash.unwrap()
     }
 
+    /// Operator keys of the members whose bit is set in signers_bitset.
+    /// `members` must be the quorum members in quorum order
+    pub fn signers_public_keys(
+        &self,
+        members: &[MasternodeEntry],
+        block_height: u32,
+    ) -> Vec<UInt384> {
+        Self::public_keys_for_bitset(&self.signers_bitset, members, block_height)
+    }
+
+    /// Operator keys of the members whose bit is set in valid_members_bitset.
+    /// `members` must be the quorum members in quorum order
+    pub fn valid_members_public_keys(
+        &self,
+        members: &[MasternodeEntry],
+        block_height: u32,
+    ) -> Vec<UInt384> {
+        Self::public_keys_for_bitset(&self.valid_members_bitset, members, block_height)
+    }
+
+    fn public_keys_for_bitset(
+        bitset: &[u8],
+        members: &[MasternodeEntry],
+        block_height: u32,
+    ) -> Vec<UInt384> {
+        members
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| matches!(bitset.get(i / 8), Some(byte) if byte & (1 << (i % 8)) != 0))
+            .map(|(_, entry)| entry.operator_public_key_at(block_height))
+            .collect()
+    }
+
     fn validate_bitset(bitset: Vec<u8>, count: VarInt) -> bool {
-        if bitset.len() != (count.0 as usize + 7) / 8 {
+        if bitset.len() != (count.0 as usize).div_ceil(8) {
             println!(
                 "Error: The byte size of the bitvectors ({}) must match “(quorumSize + 7) / 8 ({})",
                 bitset.len(),
-                (count.0 + 7) / 8
+                count.0.div_ceil(8)
             );
             return false;
         }
@@ -341,3 +372,70 @@ impl LLMQEntry {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{Block, LLMQType};
+    use crate::fixtures;
+    use crate::masternode::{LLMQEntry, MasternodeEntry};
+    use dash_spv_primitives::crypto::{UInt256, UInt384};
+
+    fn members(count: u8) -> Vec<MasternodeEntry> {
+        (1..=count).map(fixtures::masternode_entry).collect()
+    }
+
+    #[test]
+    fn bitsets_select_member_keys_in_quorum_order() {
+        let members = members(10);
+        let mut entry = fixtures::llmq_entry(LLMQType::Llmqtype50_60, 1);
+        // bit i of byte i / 8 is the member at position i
+        entry.signers_bitset = vec![0b0000_0101, 0b0000_0010];
+        entry.valid_members_bitset = vec![0xff, 0b0000_0011];
+        assert_eq!(
+            entry.signers_public_keys(&members, 10),
+            vec![UInt384([1u8; 48]), UInt384([3u8; 48]), UInt384([10u8; 48])]
+        );
+        assert_eq!(
+            entry.valid_members_public_keys(&members, 10),
+            (1..=10).map(|seed| UInt384([seed; 48])).collect::<Vec<_>>()
+        );
+        // bits past the last member and a short bitset select nothing more
+        entry.signers_bitset = vec![0b1000_0000];
+        assert_eq!(entry.signers_public_keys(&members[..4], 10), vec![]);
+        assert_eq!(entry.signers_public_keys(&members, 10), vec![UInt384([8u8; 48])]);
+    }
+
+    #[test]
+    fn huge_bitset_counts_are_incomplete() {
+        let data = fixtures::llmq_entry(LLMQType::Llmqtype50_60, 1).to_data();
+        // version, type and hash come before the signers count
+        for (count_offset, field) in [(35, "signers_bitset"), (37, "valid_members_bitset")] {
+            let mut bytes = data[..count_offset].to_vec();
+            bytes.extend([0xff; 9]);
+            bytes.extend(&data[count_offset + 1..]);
+            let err = LLMQEntry::decode(&bytes, &mut 0).unwrap_err();
+            assert_eq!((err.field(), err.offset()), (field, count_offset + 9));
+        }
+    }
+
+    #[test]
+    fn bitsets_use_operator_keys_at_quorum_height() {
+        let mut members = members(2);
+        let block = Block {
+            height: 20,
+            hash: UInt256([20u8; 32]),
+        };
+        // the operator key of the second member was changed at block 20
+        members[1].previous_operator_public_keys.insert(block, UInt384([42u8; 48]));
+        let entry = fixtures::llmq_entry(LLMQType::Llmqtype50_60, 1);
+        assert_eq!(
+            entry.signers_public_keys(&members, 10),
+            vec![UInt384([1u8; 48]), UInt384([42u8; 48])]
+        );
+        assert_eq!(
+            entry.valid_members_public_keys(&members, 20),
+            vec![UInt384([1u8; 48]), UInt384([2u8; 48])]
+        );
+    }
+}
+
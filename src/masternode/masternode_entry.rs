@@ -1,7 +1,9 @@
 use crate::common::Block;
+use crate::common::MasternodeType;
 use crate::common::SocketAddress;
+use crate::error::{read_field, Error};
 use byte::ctx::Endian;
-use byte::{BytesExt, TryRead};
+use byte::TryRead;
 use dash_spv_primitives::consensus::Encodable;
 use dash_spv_primitives::crypto::byte_util::Zeroable;
 use dash_spv_primitives::crypto::data_ops::short_hex_string_from;
@@ -9,8 +11,18 @@ use dash_spv_primitives::crypto::{UInt128, UInt160, UInt256, UInt384};
 use dash_spv_primitives::hashes::{sha256, sha256d, Hash};
 use std::collections::BTreeMap;
 
+// Entry version whose operator key uses the legacy BLS serialization
+pub const LEGACY_BLS_VERSION: u16 = 1;
+// Entry version whose operator key uses the basic BLS scheme serialization
+pub const BASIC_BLS_VERSION: u16 = 2;
+// From this protocol version on, version 2 entries are followed by the masternode type
+pub const DMN_TYPE_PROTO_VERSION: u32 = 70227;
+// From this protocol version on, every entry starts with its own nVersion
+pub const SMNLE_VERSIONED_PROTO_VERSION: u32 = 70228;
+
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct MasternodeEntry {
+    pub version: u16,
     pub provider_registration_transaction_hash: UInt256,
     pub confirmed_hash: UInt256,
     pub confirmed_hash_hashed_with_provider_registration_transaction_hash: Option<UInt256>,
@@ -23,11 +35,15 @@ pub struct MasternodeEntry {
     pub update_height: u32,
     pub key_id_voting: UInt160,
     pub is_valid: bool,
+    pub mn_type: MasternodeType,
+    pub platform_http_port: u16,
+    pub platform_node_id: UInt160,
     pub entry_hash: UInt256,
 }
 impl std::fmt::Debug for MasternodeEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("MasternodeEntry")
+            .field("version", &self.version)
             .field(
                 "provider_registration_transaction_hash",
                 &self.provider_registration_transaction_hash,
@@ -41,6 +57,7 @@ impl std::fmt::Debug for MasternodeEntry {
             // .field("previous_validity", &self.previous_validity)
             .field("known_confirmed_at_height", &self.known_confirmed_at_height.unwrap_or(0))
             .field("update_height", &self.update_height)
+            .field("mn_type", &self.mn_type)
             // .field("key_id_voting", &self.key_id_voting)
             // .field("is_valid", &self.is_valid)
             // .field("entry_hash", &self.entry_hash)
@@ -50,47 +67,98 @@ impl std::fmt::Debug for MasternodeEntry {
 impl<'a> TryRead<'a, Endian> for MasternodeEntry {
     fn try_read(bytes: &'a [u8], _ctx: Endian) -> byte::Result<(Self, usize)> {
         let offset = &mut 0;
-        let provider_registration_transaction_hash =
-            bytes.read_with::<UInt256>(offset, byte::LE)?;
-        let confirmed_hash = bytes.read_with::<UInt256>(offset, byte::LE)?;
-        let ip_address = bytes.read_with::<UInt128>(offset, byte::LE)?;
-        let port = bytes.read_with::<u16>(offset, byte::LE)?.swap_bytes();
-        let socket_address = SocketAddress { ip_address, port };
-        let operator_public_key = bytes.read_with::<UInt384>(offset, byte::LE)?;
-        let key_id_voting = bytes.read_with::<UInt160>(offset, byte::LE)?;
-        let is_valid = bytes.read_with::<u8>(offset, byte::LE).unwrap_or(0);
-        Ok((
-            Self::new(
-                provider_registration_transaction_hash,
-                confirmed_hash,
-                socket_address,
-                key_id_voting,
-                operator_public_key,
-                is_valid,
-            ),
-            *offset,
-        ))
+        let entry = Self::decode(bytes, offset)?;
+        Ok((entry, *offset))
     }
 }
 
 impl MasternodeEntry {
+    /// Decodes an entry in the layout without version fields
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        Self::decode_with_protocol_version(bytes, offset, 0, LEGACY_BLS_VERSION)
+    }
+
+    /// Decodes an entry sent with the given protocol version.
+    /// `version` is used when the entry doesn't carry its own, it's the version of the diff
+    pub fn decode_with_protocol_version(
+        bytes: &[u8],
+        offset: &mut usize,
+        protocol_version: u32,
+        version: u16,
+    ) -> Result<Self, Error> {
+        let version = if protocol_version >= SMNLE_VERSIONED_PROTO_VERSION {
+            read_field::<u16>(bytes, offset, "MasternodeEntry", "version")?
+        } else {
+            version
+        };
+        let provider_registration_transaction_hash = read_field::<UInt256>(
+            bytes,
+            offset,
+            "MasternodeEntry",
+            "provider_registration_transaction_hash",
+        )?;
+        let confirmed_hash =
+            read_field::<UInt256>(bytes, offset, "MasternodeEntry", "confirmed_hash")?;
+        let ip_address = read_field::<UInt128>(bytes, offset, "MasternodeEntry", "ip_address")?;
+        let port = read_field::<u16>(bytes, offset, "MasternodeEntry", "port")?.swap_bytes();
+        let socket_address = SocketAddress { ip_address, port };
+        let operator_public_key =
+            read_field::<UInt384>(bytes, offset, "MasternodeEntry", "operator_public_key")?;
+        let key_id_voting = read_field::<UInt160>(bytes, offset, "MasternodeEntry", "key_id_voting")?;
+        let is_valid = read_field::<u8>(bytes, offset, "MasternodeEntry", "is_valid")?;
+        let mut mn_type = MasternodeType::Regular;
+        let mut platform_http_port = 0;
+        let mut platform_node_id = UInt160::default();
+        if protocol_version >= DMN_TYPE_PROTO_VERSION && version >= BASIC_BLS_VERSION {
+            mn_type = read_field::<MasternodeType>(bytes, offset, "MasternodeEntry", "type")?;
+            if mn_type == MasternodeType::Evo {
+                platform_http_port =
+                    read_field::<u16>(bytes, offset, "MasternodeEntry", "platform_http_port")?;
+                platform_node_id =
+                    read_field::<UInt160>(bytes, offset, "MasternodeEntry", "platform_node_id")?;
+            }
+        }
+        Ok(Self::new(
+            version,
+            provider_registration_transaction_hash,
+            confirmed_hash,
+            socket_address,
+            key_id_voting,
+            operator_public_key,
+            is_valid,
+            mn_type,
+            platform_http_port,
+            platform_node_id,
+        ))
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        version: u16,
         provider_registration_transaction_hash: UInt256,
         confirmed_hash: UInt256,
         socket_address: SocketAddress,
         key_id_voting: UInt160,
         operator_public_key: UInt384,
         is_valid: u8,
+        mn_type: MasternodeType,
+        platform_http_port: u16,
+        platform_node_id: UInt160,
     ) -> Self {
         let entry_hash = MasternodeEntry::calculate_entry_hash(
+            version,
             provider_registration_transaction_hash,
             confirmed_hash,
             socket_address,
             operator_public_key,
             key_id_voting,
             is_valid,
+            mn_type,
+            platform_http_port,
+            platform_node_id,
         );
         Self {
+            version,
             provider_registration_transaction_hash,
             confirmed_hash,
             confirmed_hash_hashed_with_provider_registration_transaction_hash: Some(
@@ -105,18 +173,51 @@ impl MasternodeEntry {
             update_height: 0,
             key_id_voting,
             is_valid: is_valid != 0,
+            mn_type,
+            platform_http_port,
+            platform_node_id,
             entry_hash,
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn calculate_entry_hash(
+        version: u16,
         provider_registration_transaction_hash: UInt256,
         confirmed_hash: UInt256,
         socket_address: SocketAddress,
         operator_public_key: UInt384,
         key_id_voting: UInt160,
         is_valid: u8,
+        mn_type: MasternodeType,
+        platform_http_port: u16,
+        platform_node_id: UInt160,
     ) -> UInt256 {
+        let mut buffer = Self::generate_data(
+            provider_registration_transaction_hash,
+            confirmed_hash,
+            socket_address,
+            operator_public_key,
+            key_id_voting,
+            is_valid,
+        );
+        buffer.extend(Self::generate_type_data(
+            version,
+            mn_type,
+            platform_http_port,
+            platform_node_id,
+        ));
+        UInt256(sha256d::Hash::hash(&buffer).into_inner())
+    }
+
+    pub fn generate_data(
+        provider_registration_transaction_hash: UInt256,
+        confirmed_hash: UInt256,
+        socket_address: SocketAddress,
+        operator_public_key: UInt384,
+        key_id_voting: UInt160,
+        is_valid: u8,
+    ) -> Vec<u8> {
         let offset: &mut usize = &mut 0;
         const HASH_IMPORTANT_DATA_LENGTH: usize = 32 + 32 + 16 + 2 + 48 + 20 + 1;
         let mut buffer: Vec<u8> = Vec::with_capacity(HASH_IMPORTANT_DATA_LENGTH);
@@ -136,7 +237,66 @@ impl MasternodeEntry {
         *offset += operator_public_key.consensus_encode(&mut buffer).unwrap();
         *offset += key_id_voting.consensus_encode(&mut buffer).unwrap();
         *offset += is_valid.consensus_encode(&mut buffer).unwrap();
-        UInt256(sha256d::Hash::hash(&buffer).into_inner())
+        buffer
+    }
+
+    /// Type and platform fields following the base data of version 2 entries
+    pub fn generate_type_data(
+        version: u16,
+        mn_type: MasternodeType,
+        platform_http_port: u16,
+        platform_node_id: UInt160,
+    ) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::new();
+        if version < BASIC_BLS_VERSION {
+            return buffer;
+        }
+        (mn_type as u16).consensus_encode(&mut buffer).unwrap();
+        if mn_type == MasternodeType::Evo {
+            platform_http_port.consensus_encode(&mut buffer).unwrap();
+            platform_node_id.consensus_encode(&mut buffer).unwrap();
+        }
+        buffer
+    }
+
+    /// Encodes the entry in the layout without version fields
+    pub fn to_data(&self) -> Vec<u8> {
+        self.to_data_with_protocol_version(0)
+    }
+
+    /// Encodes the entry as sent with the given protocol version.
+    /// The entry hash covers the data of the latest protocol except nVersion,
+    /// which is only sent on the network
+    pub fn to_data_with_protocol_version(&self, protocol_version: u32) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::new();
+        if protocol_version >= SMNLE_VERSIONED_PROTO_VERSION {
+            self.version.consensus_encode(&mut buffer).unwrap();
+        }
+        buffer.extend(Self::generate_data(
+            self.provider_registration_transaction_hash,
+            self.confirmed_hash,
+            self.socket_address,
+            self.operator_public_key,
+            self.key_id_voting,
+            if self.is_valid { 1 } else { 0 },
+        ));
+        if protocol_version >= DMN_TYPE_PROTO_VERSION {
+            buffer.extend(Self::generate_type_data(
+                self.version,
+                self.mn_type,
+                self.platform_http_port,
+                self.platform_node_id,
+            ));
+        }
+        buffer
+    }
+
+    pub fn uses_legacy_bls(&self) -> bool {
+        self.version == LEGACY_BLS_VERSION
+    }
+
+    pub fn is_evonode(&self) -> bool {
+        self.mn_type == MasternodeType::Evo
     }
 
     pub fn confirmed_hash_at(&self, block_height: u32) -> Option<UInt256> {
@@ -191,12 +351,16 @@ impl MasternodeEntry {
 
     pub fn payload_data(&self) -> UInt256 {
         Self::calculate_entry_hash(
+            self.version,
             self.provider_registration_transaction_hash,
             self.confirmed_hash,
             self.socket_address,
             self.operator_public_key,
             self.key_id_voting,
             if self.is_valid { 1 } else { 0 },
+            self.mn_type,
+            self.platform_http_port,
+            self.platform_node_id,
         )
     }
 
@@ -1,12 +1,24 @@
-use crate::common::LLMQType;
+use crate::common::{Block, LLMQType};
+use crate::llmq::MNListDiff;
 use crate::masternode::{LLMQEntry, MasternodeEntry};
 use crate::tx::CoinbaseTransaction;
 use dash_spv_primitives::consensus::Encodable;
 use dash_spv_primitives::crypto::byte_util::{merkle_root_from_hashes, Reversable, Zeroable};
 use dash_spv_primitives::crypto::UInt256;
-use dash_spv_primitives::hashes::{sha256, Hash};
+use dash_spv_primitives::hashes::{sha256, sha256d, Hash};
+use std::cmp::Reverse;
 use std::collections::BTreeMap;
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MasternodeListError {
+    // The diff was built on top of a different list
+    BaseBlockMismatch,
+    // Computed masternode merkle root doesn't match the one committed in the coinbase
+    InvalidMasternodeMerkleRoot,
+    // Computed llmq merkle root doesn't match the one committed in the coinbase
+    InvalidLLMQMerkleRoot,
+}
+
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct MasternodeList {
     pub block_hash: UInt256,
@@ -69,6 +81,75 @@ impl MasternodeList {
         list
     }
 
+    /// Produces the list at `diff.block_hash` by merging the diff into this (base) list
+    /// and checks the resulting merkle roots against the diff's coinbase transaction.
+    /// A diff with a zero base block hash carries the full list so it's applied to an empty one
+    pub fn apply_diff(&self, diff: &MNListDiff) -> Result<MasternodeList, MasternodeListError> {
+        let is_full_list = diff.base_block_hash.is_zero();
+        if !is_full_list && diff.base_block_hash != self.block_hash {
+            return Err(MasternodeListError::BaseBlockMismatch);
+        }
+        let block = Block {
+            height: diff.block_height,
+            hash: diff.block_hash,
+        };
+        let (mut masternodes, mut quorums) = if is_full_list {
+            (BTreeMap::new(), BTreeMap::new())
+        } else {
+            (self.masternodes.clone(), self.quorums.clone())
+        };
+        for hash in &diff.deleted_masternode_hashes {
+            masternodes.remove(&hash.clone().reversed());
+        }
+        for (hash, entry) in &diff.added_or_modified_masternodes {
+            let mut entry = entry.clone();
+            if let Some(old) = masternodes.get_mut(hash) {
+                if old.update_height < entry.update_height {
+                    entry.update_with_previous_entry(old, block);
+                }
+                if old.confirmed_hash == entry.confirmed_hash {
+                    // keep the earliest height we've seen this confirmation at
+                    if let Some(old_height) = old.known_confirmed_at_height {
+                        match entry.known_confirmed_at_height {
+                            Some(height) if height <= old_height => {}
+                            _ => entry.known_confirmed_at_height = Some(old_height),
+                        }
+                    }
+                }
+            }
+            masternodes.insert(*hash, entry);
+        }
+        for (llmq_type, hashes) in &diff.deleted_quorums {
+            if let Some(map) = quorums.get_mut(llmq_type) {
+                for hash in hashes {
+                    map.remove(hash);
+                }
+            }
+        }
+        for (llmq_type, entries) in &diff.added_quorums {
+            let map = quorums.entry(*llmq_type).or_default();
+            for (hash, entry) in entries {
+                map.insert(*hash, entry.clone());
+            }
+        }
+        quorums.retain(|_, map| !map.is_empty());
+        let quorums_active = diff.coinbase_transaction.coinbase_transaction_version >= 2;
+        let list = MasternodeList::new(
+            masternodes,
+            quorums,
+            diff.block_hash,
+            diff.block_height,
+            quorums_active,
+        );
+        if !list.has_valid_mn_list_root(&diff.coinbase_transaction) {
+            return Err(MasternodeListError::InvalidMasternodeMerkleRoot);
+        }
+        if quorums_active && !list.has_valid_llmq_list_root(&diff.coinbase_transaction) {
+            return Err(MasternodeListError::InvalidLLMQMerkleRoot);
+        }
+        Ok(list)
+    }
+
     pub fn quorums_count(&self) -> u64 {
         let mut count: u64 = 0;
         for entry in self.quorums.values() {
@@ -114,6 +195,21 @@ impl MasternodeList {
         llmq_commitment_hashes
     }
 
+    /// Evonodes of the list, the only masternodes serving Dash Platform (DAPI)
+    pub fn evonodes(&self) -> Vec<&MasternodeEntry> {
+        self.masternodes
+            .values()
+            .filter(|entry| entry.is_evonode())
+            .collect()
+    }
+
+    pub fn valid_evonodes(&self) -> Vec<&MasternodeEntry> {
+        self.masternodes
+            .values()
+            .filter(|entry| entry.is_evonode() && entry.is_valid)
+            .collect()
+    }
+
     pub fn masternode_for(&self, registration_hash: UInt256) -> Option<&MasternodeEntry> {
         self.masternodes.get(&registration_hash)
     }
@@ -169,6 +265,54 @@ impl MasternodeList {
         }
     }
 
+    /// Quorum modifier of a non-rotated quorum: SerializeHash(llmq_type, block_hash)
+    pub fn quorum_modifier(llmq_type: LLMQType, block_hash: UInt256) -> UInt256 {
+        let mut buffer: Vec<u8> = Vec::with_capacity(33);
+        let offset: &mut usize = &mut 0;
+        let llmq_u8: u8 = llmq_type.into();
+        *offset += llmq_u8.consensus_encode(&mut buffer).unwrap();
+        *offset += block_hash.consensus_encode(&mut buffer).unwrap();
+        UInt256(sha256d::Hash::hash(&buffer).into_inner())
+    }
+
+    /// Sorts masternodes by their score for the modifier, highest first.
+    /// Masternodes which have no score at block_height (invalid or unconfirmed) are left out
+    pub fn sort_masternodes_by_score(
+        masternodes: Vec<MasternodeEntry>,
+        modifier: UInt256,
+        block_height: u32,
+    ) -> Vec<MasternodeEntry> {
+        let mut scored_masternodes: Vec<(UInt256, MasternodeEntry)> = masternodes
+            .into_iter()
+            .filter_map(|entry| {
+                Self::masternode_score(&entry, modifier, block_height).map(|score| (score, entry))
+            })
+            .collect();
+        scored_masternodes.sort_by_key(|(score, _)| Reverse(score.clone().reversed()));
+        scored_masternodes
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect()
+    }
+
+    /// Members of the non-rotated quorum built at quorum_hash, in quorum order.
+    /// The list is expected to be the one at the quorum block
+    pub fn valid_masternodes_for_quorum(
+        &self,
+        llmq_type: LLMQType,
+        quorum_hash: UInt256,
+        block_height: u32,
+    ) -> Vec<MasternodeEntry> {
+        let modifier = Self::quorum_modifier(llmq_type, quorum_hash);
+        let mut members = Self::sort_masternodes_by_score(
+            self.masternodes.values().cloned().collect(),
+            modifier,
+            block_height,
+        );
+        members.truncate(llmq_type.size() as usize);
+        members
+    }
+
     pub fn quorum_entry_for_platform_with_quorum_hash(
         &self,
         quorum_hash: UInt256,
@@ -209,3 +353,262 @@ impl MasternodeList {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{Block, LLMQType, MasternodeType};
+    use crate::fixtures;
+    use crate::llmq::MNListDiff;
+    use crate::masternode::masternode_list::MasternodeListError;
+    use crate::masternode::{MasternodeEntry, MasternodeList};
+    use dash_spv_primitives::crypto::byte_util::Reversable;
+    use dash_spv_primitives::crypto::{UInt256, UInt384};
+    use dash_spv_primitives::hashes::{sha256, sha256d, Hash};
+    use std::collections::BTreeMap;
+
+    fn key(entry: &MasternodeEntry) -> UInt256 {
+        entry.provider_registration_transaction_hash.clone().reversed()
+    }
+
+    /// Makes the diff's coinbase commit to the roots of the expected list
+    fn commit_to(diff: &mut MNListDiff, expected: &MasternodeList) {
+        diff.coinbase_transaction.merkle_root_mn_list = expected.masternode_merkle_root.unwrap();
+        diff.coinbase_transaction.merkle_root_llmq_list = expected.llmq_merkle_root;
+    }
+
+    #[test]
+    fn full_list_diff_starts_from_empty_list() {
+        let base = fixtures::masternode_list(UInt256([1u8; 32]), 5, &[1, 2]);
+        let mut diff = fixtures::mn_list_diff(UInt256::MIN, UInt256([2u8; 32]), 10);
+        diff.block_height = 6;
+        let expected = MasternodeList::new(
+            diff.added_or_modified_masternodes.clone(),
+            diff.added_quorums.clone(),
+            diff.block_hash,
+            6,
+            true,
+        );
+        commit_to(&mut diff, &expected);
+        // the base list is dropped whatever it is
+        for list in [base, MasternodeList::default()] {
+            let list = list.apply_diff(&diff).expect("diff should be applied");
+            assert_eq!(list.masternodes, expected.masternodes);
+            assert_eq!(list.quorums, expected.quorums);
+            assert_eq!(list.block_hash, diff.block_hash);
+            assert_eq!(list.known_height, 6);
+        }
+    }
+
+    #[test]
+    fn diff_adds_modifies_and_deletes_entries() {
+        let base_hash = UInt256([1u8; 32]);
+        let block_hash = UInt256([2u8; 32]);
+        let mut base = fixtures::masternode_list(base_hash, 5, &[1, 2, 3]);
+        let q1 = fixtures::llmq_entry(LLMQType::Llmqtype50_60, 1);
+        let q2 = fixtures::llmq_entry(LLMQType::Llmqtype50_60, 2);
+        let q3 = fixtures::llmq_entry(LLMQType::Llmqtype60_75, 3);
+        base.quorums.insert(
+            LLMQType::Llmqtype50_60,
+            BTreeMap::from([(q1.llmq_hash, q1.clone()), (q2.llmq_hash, q2.clone())]),
+        );
+        let old = base.masternodes[&key(&fixtures::masternode_entry(2))].clone();
+        let mut modified = fixtures::masternode_entry(2);
+        modified.operator_public_key = UInt384([42u8; 48]);
+        modified.entry_hash = modified.payload_data();
+        modified.update_with_block_height(6);
+        let mut added = fixtures::masternode_entry(4);
+        added.update_with_block_height(6);
+
+        let mut diff = fixtures::mn_list_diff(base_hash, block_hash, 10);
+        diff.block_height = 6;
+        diff.deleted_masternode_hashes =
+            vec![fixtures::masternode_entry(1).provider_registration_transaction_hash];
+        diff.added_or_modified_masternodes =
+            BTreeMap::from([(key(&modified), modified.clone()), (key(&added), added.clone())]);
+        diff.deleted_quorums = BTreeMap::from([(LLMQType::Llmqtype50_60, vec![q1.llmq_hash])]);
+        diff.added_quorums =
+            BTreeMap::from([(LLMQType::Llmqtype60_75, BTreeMap::from([(q3.llmq_hash, q3)]))]);
+
+        let mut masternodes = diff.added_or_modified_masternodes.clone();
+        let kept = base.masternodes[&key(&fixtures::masternode_entry(3))].clone();
+        masternodes.insert(key(&kept), kept);
+        let mut quorums = diff.added_quorums.clone();
+        quorums.insert(LLMQType::Llmqtype50_60, BTreeMap::from([(q2.llmq_hash, q2)]));
+        let expected = MasternodeList::new(masternodes, quorums, block_hash, 6, true);
+        commit_to(&mut diff, &expected);
+
+        let list = base.apply_diff(&diff).expect("diff should be applied");
+        assert_eq!(
+            list.masternodes.keys().collect::<Vec<_>>(),
+            expected.masternodes.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(list.quorums, expected.quorums);
+        assert_eq!(list.masternode_merkle_root, expected.masternode_merkle_root);
+        assert_eq!(list.llmq_merkle_root, expected.llmq_merkle_root);
+        assert_eq!(list.masternodes[&key(&added)], added);
+
+        // the modified entry keeps track of what it was before the diff block
+        let entry = &list.masternodes[&key(&modified)];
+        let block = Block {
+            height: 6,
+            hash: block_hash,
+        };
+        assert_eq!(entry.operator_public_key, UInt384([42u8; 48]));
+        assert_eq!(
+            entry.previous_operator_public_keys,
+            BTreeMap::from([(block, old.operator_public_key)])
+        );
+        assert_eq!(entry.previous_entry_hashes, BTreeMap::from([(block, old.entry_hash)]));
+        assert!(entry.previous_validity.is_empty());
+        assert_eq!(entry.operator_public_key_at(5), old.operator_public_key);
+        assert_eq!(entry.entry_hash_at(5), old.entry_hash);
+        assert_eq!(entry.entry_hash_at(6), modified.entry_hash);
+        assert_ne!(modified.entry_hash, old.entry_hash);
+        // the confirmation didn't change so the earliest known height is kept
+        assert_eq!(entry.known_confirmed_at_height, Some(1));
+    }
+
+    #[test]
+    fn diff_with_wrong_base_or_roots_is_rejected() {
+        let base_hash = UInt256([1u8; 32]);
+        let base = fixtures::masternode_list(base_hash, 5, &[1, 2]);
+        let mut diff = fixtures::mn_list_diff(base_hash, UInt256([2u8; 32]), 10);
+        diff.deleted_quorums.clear();
+        let mut masternodes = base.masternodes.clone();
+        masternodes.extend(diff.added_or_modified_masternodes.clone());
+        let expected =
+            MasternodeList::new(masternodes, diff.added_quorums.clone(), diff.block_hash, 0, true);
+        commit_to(&mut diff, &expected);
+        assert!(base.apply_diff(&diff).is_ok());
+
+        let mut other_base = base.clone();
+        other_base.block_hash = UInt256([3u8; 32]);
+        assert_eq!(
+            other_base.apply_diff(&diff).unwrap_err(),
+            MasternodeListError::BaseBlockMismatch
+        );
+
+        let mut wrong_mn_root = diff.clone();
+        wrong_mn_root.coinbase_transaction.merkle_root_mn_list = UInt256([9u8; 32]);
+        assert_eq!(
+            base.apply_diff(&wrong_mn_root).unwrap_err(),
+            MasternodeListError::InvalidMasternodeMerkleRoot
+        );
+
+        let mut wrong_llmq_root = diff.clone();
+        wrong_llmq_root.coinbase_transaction.merkle_root_llmq_list = Some(UInt256([9u8; 32]));
+        assert_eq!(
+            base.apply_diff(&wrong_llmq_root).unwrap_err(),
+            MasternodeListError::InvalidLLMQMerkleRoot
+        );
+        wrong_llmq_root.coinbase_transaction.merkle_root_llmq_list = None;
+        assert_eq!(
+            base.apply_diff(&wrong_llmq_root).unwrap_err(),
+            MasternodeListError::InvalidLLMQMerkleRoot
+        );
+    }
+
+    #[test]
+    fn quorum_modifier_hashes_type_and_block_hash() {
+        let quorum_hash = UInt256([7u8; 32]);
+        let mut data = vec![100u8];
+        data.extend_from_slice(&quorum_hash.0);
+        assert_eq!(
+            MasternodeList::quorum_modifier(LLMQType::LlmqtypeTest, quorum_hash),
+            UInt256(sha256d::Hash::hash(&data).into_inner())
+        );
+        assert_ne!(
+            MasternodeList::quorum_modifier(LLMQType::LlmqtypeTest, quorum_hash),
+            MasternodeList::quorum_modifier(LLMQType::Llmqtype50_60, quorum_hash)
+        );
+    }
+
+    #[test]
+    fn quorum_members_are_ordered_by_descending_score() {
+        let quorum_hash = UInt256([7u8; 32]);
+        let mut list = fixtures::masternode_list(UInt256([1u8; 32]), 10, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        for entry in list.masternodes.values_mut() {
+            match entry.provider_registration_transaction_hash.0[0] {
+                // banned
+                3 => entry.is_valid = false,
+                // confirmed after the quorum block
+                5 => entry.known_confirmed_at_height = Some(11),
+                // not confirmed at all
+                6 => entry.update_confirmed_hash(UInt256::MIN),
+                _ => {}
+            }
+        }
+        let modifier = MasternodeList::quorum_modifier(LLMQType::LlmqtypeTest, quorum_hash);
+        // score = SHA256(SHA256(proTxHash, confirmedHash), modifier), compared as a number
+        let mut expected: Vec<(UInt256, UInt256)> = list
+            .masternodes
+            .values()
+            .filter(|entry| ![3, 5, 6].contains(&entry.provider_registration_transaction_hash.0[0]))
+            .map(|entry| {
+                let pro_reg_tx_hash = entry.provider_registration_transaction_hash;
+                let mut data = pro_reg_tx_hash.0.to_vec();
+                data.extend_from_slice(&entry.confirmed_hash.0);
+                let mut data = sha256::Hash::hash(&data).into_inner().to_vec();
+                data.extend_from_slice(&modifier.0);
+                let score = UInt256(sha256::Hash::hash(&data).into_inner());
+                (score.clone().reversed(), pro_reg_tx_hash)
+            })
+            .collect();
+        expected.sort();
+        expected.reverse();
+        let expected: Vec<UInt256> = expected.into_iter().map(|(_, hash)| hash).collect();
+        assert_eq!(expected.len(), 5);
+
+        let sorted: Vec<UInt256> = MasternodeList::sort_masternodes_by_score(
+            list.masternodes.values().cloned().collect(),
+            modifier,
+            10,
+        )
+        .iter()
+        .map(|entry| entry.provider_registration_transaction_hash)
+        .collect();
+        assert_eq!(sorted, expected);
+
+        // the test quorum is smaller than the valid list and takes the top scored ones
+        let members: Vec<UInt256> = list
+            .valid_masternodes_for_quorum(LLMQType::LlmqtypeTest, quorum_hash, 10)
+            .iter()
+            .map(|entry| entry.provider_registration_transaction_hash)
+            .collect();
+        let size = LLMQType::LlmqtypeTest.size() as usize;
+        assert!(size < expected.len());
+        assert_eq!(members, expected[..size]);
+        // larger quorums take every valid masternode
+        let members = list.valid_masternodes_for_quorum(LLMQType::Llmqtype50_60, quorum_hash, 10);
+        assert_eq!(members.len(), 5);
+    }
+
+    #[test]
+    fn evonodes_are_filtered_by_type_and_validity() {
+        let mut list = fixtures::masternode_list(UInt256([1u8; 32]), 10, &[1, 2, 3, 4, 5]);
+        for entry in list.masternodes.values_mut() {
+            match entry.provider_registration_transaction_hash.0[0] {
+                2 | 4 => entry.mn_type = MasternodeType::Evo,
+                5 => {
+                    entry.mn_type = MasternodeType::Evo;
+                    entry.is_valid = false;
+                }
+                // a banned regular masternode isn't an evonode either
+                3 => entry.is_valid = false,
+                _ => {}
+            }
+        }
+        let seeds = |entries: Vec<&MasternodeEntry>| -> Vec<u8> {
+            entries
+                .iter()
+                .map(|entry| entry.provider_registration_transaction_hash.0[0])
+                .collect()
+        };
+        assert_eq!(seeds(list.evonodes()), vec![2, 4, 5]);
+        assert_eq!(seeds(list.valid_evonodes()), vec![2, 4]);
+
+        let regular = fixtures::masternode_list(UInt256([1u8; 32]), 10, &[1, 2]);
+        assert!(regular.evonodes().is_empty());
+        assert!(regular.valid_evonodes().is_empty());
+    }
+}
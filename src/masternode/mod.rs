@@ -8,7 +8,7 @@ pub mod masternode_list;
 
 pub use self::llmq_entry::LLMQEntry;
 pub use self::masternode_entry::MasternodeEntry;
-pub use self::masternode_list::MasternodeList;
+pub use self::masternode_list::{MasternodeList, MasternodeListError};
 
 impl_bytes_decodable!(MasternodeEntry);
 impl_bytes_decodable!(LLMQEntry);
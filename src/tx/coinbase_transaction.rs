@@ -1,10 +1,12 @@
-use crate::tx::Transaction;
+use crate::error::{read_field, Error};
+use crate::tx::transaction::ITransaction;
 use crate::tx::TransactionType::Coinbase;
+use crate::tx::{Transaction, TransactionType};
 use byte::ctx::Endian;
-use byte::{BytesExt, TryRead};
+use byte::TryRead;
 use dash_spv_primitives::consensus::encode::VarInt;
 use dash_spv_primitives::consensus::Encodable;
-use dash_spv_primitives::crypto::UInt256;
+use dash_spv_primitives::crypto::{UInt256, UInt768};
 use dash_spv_primitives::hashes::{sha256d, Hash};
 
 #[derive(Debug, Clone)]
@@ -14,37 +16,106 @@ pub struct CoinbaseTransaction {
     pub height: u32,
     pub merkle_root_mn_list: UInt256,
     pub merkle_root_llmq_list: Option<UInt256>,
+    // Fields below are part of the payload from version 3 on, as in Dash Core they're
+    // always set so that a v3 coinbase can't be encoded without them
+    // Distance to the height of the best ChainLock known to the block: height - 1 - best_cl_height
+    pub best_cl_height_diff: u64,
+    pub best_cl_signature: UInt768,
+    // Balance of the platform credit pool in duffs
+    pub credit_pool_balance: i64,
 }
 
 impl<'a> TryRead<'a, Endian> for CoinbaseTransaction {
-    fn try_read(bytes: &'a [u8], endian: Endian) -> byte::Result<(Self, usize)> {
+    fn try_read(bytes: &'a [u8], _endian: Endian) -> byte::Result<(Self, usize)> {
         let offset = &mut 0;
-        let mut base = bytes.read_with::<Transaction>(offset, endian)?;
-        let _extra_payload_size = bytes.read_with::<VarInt>(offset, endian)?;
-        let coinbase_transaction_version = bytes.read_with::<u16>(offset, endian)?;
-        let height = bytes.read_with::<u32>(offset, endian)?;
-        let merkle_root_mn_list = bytes.read_with::<UInt256>(offset, endian)?;
-        let merkle_root_llmq_list = if coinbase_transaction_version >= 2 {
-            let root = bytes.read_with::<UInt256>(offset, endian)?;
-            Some(root)
-        } else {
-            None
-        };
-        base.tx_type = Coinbase;
-        base.payload_offset = *offset;
-        let mut tx = Self {
-            base,
-            coinbase_transaction_version,
-            height,
-            merkle_root_mn_list,
-            merkle_root_llmq_list,
-        };
-        tx.base.tx_hash = Some(UInt256(sha256d::Hash::hash(&tx.to_data()).into_inner()));
+        let tx = Self::decode(bytes, offset)?;
         Ok((tx, *offset))
     }
 }
 
 impl CoinbaseTransaction {
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        const STRUCTURE: &str = "CoinbaseTransaction";
+        Transaction::decode_special(bytes, offset, STRUCTURE, Coinbase, |base, bytes, offset| {
+            let coinbase_transaction_version =
+                read_field::<u16>(bytes, offset, STRUCTURE, "coinbase_transaction_version")?;
+            let height = read_field::<u32>(bytes, offset, STRUCTURE, "height")?;
+            let merkle_root_mn_list =
+                read_field::<UInt256>(bytes, offset, STRUCTURE, "merkle_root_mn_list")?;
+            let merkle_root_llmq_list = if coinbase_transaction_version >= 2 {
+                let root =
+                    read_field::<UInt256>(bytes, offset, STRUCTURE, "merkle_root_llmq_list")?;
+                Some(root)
+            } else {
+                None
+            };
+            let (best_cl_height_diff, best_cl_signature, credit_pool_balance) =
+                if coinbase_transaction_version >= 3 {
+                    let height_diff =
+                        read_field::<VarInt>(bytes, offset, STRUCTURE, "best_cl_height_diff")?;
+                    let signature =
+                        read_field::<UInt768>(bytes, offset, STRUCTURE, "best_cl_signature")?;
+                    let balance =
+                        read_field::<i64>(bytes, offset, STRUCTURE, "credit_pool_balance")?;
+                    (height_diff.0, signature, balance)
+                } else {
+                    (0, UInt768::default(), 0)
+                };
+            // fields of later versions are skipped, they're still part of the txid
+            // as it's computed over the raw payload
+            *offset = bytes.len();
+            Ok(Self {
+                base,
+                coinbase_transaction_version,
+                height,
+                merkle_root_mn_list,
+                merkle_root_llmq_list,
+                best_cl_height_diff,
+                best_cl_signature,
+                credit_pool_balance,
+            })
+        })
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        self.to_data_with_subscript_index(u64::MAX)
+    }
+
+    pub fn to_data_with_subscript_index(&self, subscript_index: u64) -> Vec<u8> {
+        self.base
+            .to_data_with_payload(subscript_index, &self.payload_data())
+    }
+
+    /// Height and signature of the best ChainLock known to the block (CbTx v3)
+    pub fn best_chain_lock(&self) -> Option<(u32, UInt768)> {
+        if self.coinbase_transaction_version < 3 {
+            return None;
+        }
+        let height_diff = u32::try_from(self.best_cl_height_diff).ok()?;
+        let height = self.height.checked_sub(height_diff)?.checked_sub(1)?;
+        Some((height, self.best_cl_signature))
+    }
+
+    pub fn has_found_coinbase(&mut self, hashes: &[UInt256]) -> bool {
+        if let Some(coinbase_hash) = self.base.tx_hash {
+            self.has_found_coinbase_internal(coinbase_hash, hashes)
+        } else {
+            let coinbase_hash = UInt256(sha256d::Hash::hash(&self.to_data()).into_inner());
+            self.base.tx_hash = Some(coinbase_hash);
+            self.has_found_coinbase_internal(coinbase_hash, hashes)
+        }
+    }
+
+    fn has_found_coinbase_internal(&self, coinbase_hash: UInt256, hashes: &[UInt256]) -> bool {
+        hashes
+            .iter()
+            .filter(|&h| coinbase_hash.cmp(h).is_eq())
+            .count()
+            > 0
+    }
+}
+
+impl ITransaction for CoinbaseTransaction {
     fn payload_data(&self) -> Vec<u8> {
         let mut buffer: Vec<u8> = Vec::new();
         let offset: &mut usize = &mut 0;
@@ -62,43 +133,87 @@ impl CoinbaseTransaction {
                 *offset += llmq_list.consensus_encode(&mut buffer).unwrap();
             }
         }
+        if self.coinbase_transaction_version >= 3 {
+            *offset += VarInt(self.best_cl_height_diff)
+                .consensus_encode(&mut buffer)
+                .unwrap();
+            *offset += self.best_cl_signature.consensus_encode(&mut buffer).unwrap();
+            *offset += self.credit_pool_balance.consensus_encode(&mut buffer).unwrap();
+        }
         buffer
     }
 
-    pub fn to_data(&self) -> Vec<u8> {
-        self.to_data_with_subscript_index(u64::MAX)
+    fn payload_data_for(&self) -> Vec<u8> {
+        self.payload_data()
     }
 
-    pub fn to_data_with_subscript_index(&self, subscript_index: u64) -> Vec<u8> {
-        let mut buffer = Transaction::data_with_subscript_index_static(
-            subscript_index,
-            self.base.version,
-            self.base.tx_type,
-            &self.base.inputs,
-            &self.base.outputs,
-            self.base.lock_time,
-        );
-        let offset: &mut usize = &mut 0;
-        let payload = self.payload_data();
-        *offset += payload.consensus_encode(&mut buffer).unwrap();
-        buffer
+    fn transaction_type(&self) -> TransactionType {
+        Coinbase
     }
+}
 
-    pub fn has_found_coinbase(&mut self, hashes: &[UInt256]) -> bool {
-        if let Some(coinbase_hash) = self.base.tx_hash {
-            self.has_found_coinbase_internal(coinbase_hash, hashes)
-        } else {
-            let coinbase_hash = UInt256(sha256d::Hash::hash(&self.to_data()).into_inner());
-            self.base.tx_hash = Some(coinbase_hash);
-            self.has_found_coinbase_internal(coinbase_hash, hashes)
-        }
+#[cfg(test)]
+mod tests {
+    use crate::fixtures;
+    use crate::tx::CoinbaseTransaction;
+    use dash_spv_primitives::crypto::{UInt256, UInt768};
+    use dash_spv_primitives::hashes::{sha256d, Hash};
+
+    #[test]
+    fn coinbase_v3_round_trip() {
+        let mut tx = fixtures::coinbase_transaction(1000);
+        tx.coinbase_transaction_version = 3;
+        tx.best_cl_height_diff = 4;
+        tx.best_cl_signature = UInt768([5u8; 96]);
+        tx.credit_pool_balance = 1_000_000;
+        let data = tx.to_data();
+        let parsed = CoinbaseTransaction::decode(&data, &mut 0).expect("cbtx should be parsed");
+        assert_eq!(parsed.to_data(), data);
+        assert_eq!(parsed.credit_pool_balance, 1_000_000);
+        assert_eq!(parsed.best_chain_lock(), Some((995, UInt768([5u8; 96]))));
+        // the v3 fields are part of the txid
+        let mut other = parsed.clone();
+        other.credit_pool_balance = 0;
+        assert_ne!(other.to_data(), data);
     }
 
-    fn has_found_coinbase_internal(&self, coinbase_hash: UInt256, hashes: &[UInt256]) -> bool {
-        hashes
-            .iter()
-            .filter(|&h| coinbase_hash.cmp(h).is_eq())
-            .count()
-            > 0
+    #[test]
+    fn coinbase_v3_fields_are_always_encoded() {
+        let mut tx = fixtures::coinbase_transaction(1000);
+        let v2_data = tx.to_data();
+        assert_eq!(tx.best_chain_lock(), None);
+        // a v3 coinbase without a known ChainLock still carries the fields
+        tx.coinbase_transaction_version = 3;
+        let data = tx.to_data();
+        assert_eq!(data.len(), v2_data.len() + 1 + 96 + 8);
+        let parsed = CoinbaseTransaction::decode(&data, &mut 0).expect("cbtx should be parsed");
+        assert_eq!(parsed.best_cl_height_diff, 0);
+        assert_eq!(parsed.best_cl_signature, UInt768::default());
+        assert_eq!(parsed.credit_pool_balance, 0);
+        assert_eq!(parsed.best_chain_lock(), Some((999, UInt768::default())));
+        assert_eq!(parsed.to_data(), data);
+        // and they're left out of older versions whatever their value
+        let mut v2 = parsed;
+        v2.coinbase_transaction_version = 2;
+        v2.credit_pool_balance = 5;
+        assert_eq!(v2.to_data(), v2_data);
+    }
+
+    #[test]
+    fn unknown_payload_fields_are_skipped() {
+        let tx = fixtures::coinbase_transaction(1000);
+        let mut data = tx.to_data();
+        // declare and append one more payload byte, then something following the transaction
+        let payload_size_position = data.len() - 71;
+        assert_eq!(data[payload_size_position], 70);
+        data[payload_size_position] = 71;
+        data.extend([0xff, 0xee]);
+        let offset = &mut 0;
+        let tx = CoinbaseTransaction::decode(&data, offset).expect("cbtx should be parsed");
+        assert_eq!(*offset, data.len() - 1);
+        assert_eq!(
+            tx.base.tx_hash,
+            Some(UInt256(sha256d::Hash::hash(&data[..data.len() - 1]).into_inner()))
+        );
     }
 }
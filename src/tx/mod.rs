@@ -3,13 +3,29 @@ use dash_spv_primitives::crypto::byte_util::BytesDecodable;
 use dash_spv_primitives::impl_bytes_decodable;
 
 pub mod coinbase_transaction;
+pub mod provider_registration_transaction;
+pub mod provider_update_registrar_transaction;
+pub mod provider_update_revocation_transaction;
+pub mod provider_update_service_transaction;
+pub mod quorum_commitment_transaction;
+pub mod script;
 pub mod transaction;
+pub mod transaction_builder;
+pub mod typed_transaction;
 
 pub use self::coinbase_transaction::CoinbaseTransaction;
+pub use self::provider_registration_transaction::ProviderRegistrationTransaction;
+pub use self::provider_update_registrar_transaction::ProviderUpdateRegistrarTransaction;
+pub use self::provider_update_revocation_transaction::ProviderUpdateRevocationTransaction;
+pub use self::provider_update_service_transaction::ProviderUpdateServiceTransaction;
+pub use self::quorum_commitment_transaction::QuorumCommitmentTransaction;
+pub use self::transaction::ITransaction;
 pub use self::transaction::Transaction;
 pub use self::transaction::TransactionInput;
 pub use self::transaction::TransactionOutput;
 pub use self::transaction::TransactionType;
+pub use self::transaction_builder::TransactionBuilder;
+pub use self::typed_transaction::TypedTransaction;
 
 // impl_bytes_decodable_lt!(TransactionInput);
 // impl_bytes_decodable_lt!(TransactionOutput);
@@ -19,3 +35,9 @@ impl_bytes_decodable!(TransactionInput);
 impl_bytes_decodable!(TransactionOutput);
 impl_bytes_decodable!(Transaction);
 impl_bytes_decodable!(CoinbaseTransaction);
+impl_bytes_decodable!(ProviderRegistrationTransaction);
+impl_bytes_decodable!(ProviderUpdateServiceTransaction);
+impl_bytes_decodable!(ProviderUpdateRegistrarTransaction);
+impl_bytes_decodable!(ProviderUpdateRevocationTransaction);
+impl_bytes_decodable!(QuorumCommitmentTransaction);
+impl_bytes_decodable!(TypedTransaction);
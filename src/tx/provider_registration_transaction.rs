@@ -0,0 +1,184 @@
+use crate::common::{ChainType, MasternodeType, SocketAddress};
+use crate::error::{read_field, Error};
+use crate::masternode::masternode_entry::BASIC_BLS_VERSION;
+use crate::tx::script::{address_for_chain, ScriptType};
+use crate::tx::transaction::ITransaction;
+use crate::tx::{Transaction, TransactionType};
+use byte::ctx::Endian;
+use byte::TryRead;
+use dash_spv_primitives::consensus::Encodable;
+use dash_spv_primitives::crypto::{UInt128, UInt160, UInt256, UInt384, VarBytes};
+
+/// ProRegTx: registers a masternode
+#[derive(Debug, Clone)]
+pub struct ProviderRegistrationTransaction {
+    pub base: Transaction,
+    pub provider_registration_transaction_version: u16,
+    pub provider_type: MasternodeType,
+    pub provider_mode: u16,
+    pub collateral_hash: UInt256,
+    pub collateral_index: u32,
+    pub socket_address: SocketAddress,
+    pub owner_key_hash: UInt160,
+    pub operator_key: UInt384,
+    pub voting_key_hash: UInt160,
+    // Share of the reward going to the operator, in hundredths of a percent
+    pub operator_reward: u16,
+    pub script_payout: Vec<u8>,
+    pub inputs_hash: UInt256,
+    // Evonodes only
+    pub platform_node_id: UInt160,
+    pub platform_p2p_port: u16,
+    pub platform_http_port: u16,
+    // Signed by the collateral key, empty when the collateral is an output of this transaction
+    pub payload_signature: Vec<u8>,
+}
+
+impl<'a> TryRead<'a, Endian> for ProviderRegistrationTransaction {
+    fn try_read(bytes: &'a [u8], _endian: Endian) -> byte::Result<(Self, usize)> {
+        let offset = &mut 0;
+        let tx = Self::decode(bytes, offset)?;
+        Ok((tx, *offset))
+    }
+}
+
+impl ProviderRegistrationTransaction {
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        const STRUCTURE: &str = "ProviderRegistrationTransaction";
+        Transaction::decode_special(
+            bytes,
+            offset,
+            STRUCTURE,
+            TransactionType::ProviderRegistration,
+            |base, bytes, offset| {
+                let provider_registration_transaction_version =
+                    read_field::<u16>(bytes, offset, STRUCTURE, "version")?;
+                let provider_type = read_field::<MasternodeType>(bytes, offset, STRUCTURE, "type")?;
+                let provider_mode = read_field::<u16>(bytes, offset, STRUCTURE, "mode")?;
+                let collateral_hash =
+                    read_field::<UInt256>(bytes, offset, STRUCTURE, "collateral_hash")?;
+                let collateral_index =
+                    read_field::<u32>(bytes, offset, STRUCTURE, "collateral_index")?;
+                let ip_address = read_field::<UInt128>(bytes, offset, STRUCTURE, "ip_address")?;
+                let port = read_field::<u16>(bytes, offset, STRUCTURE, "port")?.swap_bytes();
+                let owner_key_hash =
+                    read_field::<UInt160>(bytes, offset, STRUCTURE, "owner_key_hash")?;
+                let operator_key = read_field::<UInt384>(bytes, offset, STRUCTURE, "operator_key")?;
+                let voting_key_hash =
+                    read_field::<UInt160>(bytes, offset, STRUCTURE, "voting_key_hash")?;
+                let operator_reward =
+                    read_field::<u16>(bytes, offset, STRUCTURE, "operator_reward")?;
+                let script_payout =
+                    read_field::<VarBytes>(bytes, offset, STRUCTURE, "script_payout")?;
+                let inputs_hash = read_field::<UInt256>(bytes, offset, STRUCTURE, "inputs_hash")?;
+                let mut platform_node_id = UInt160::default();
+                let mut platform_p2p_port = 0;
+                let mut platform_http_port = 0;
+                if provider_registration_transaction_version >= BASIC_BLS_VERSION
+                    && provider_type == MasternodeType::Evo
+                {
+                    platform_node_id =
+                        read_field::<UInt160>(bytes, offset, STRUCTURE, "platform_node_id")?;
+                    platform_p2p_port =
+                        read_field::<u16>(bytes, offset, STRUCTURE, "platform_p2p_port")?;
+                    platform_http_port =
+                        read_field::<u16>(bytes, offset, STRUCTURE, "platform_http_port")?;
+                }
+                let payload_signature =
+                    read_field::<VarBytes>(bytes, offset, STRUCTURE, "payload_signature")?;
+                Ok(Self {
+                    base,
+                    provider_registration_transaction_version,
+                    provider_type,
+                    provider_mode,
+                    collateral_hash,
+                    collateral_index,
+                    socket_address: SocketAddress { ip_address, port },
+                    owner_key_hash,
+                    operator_key,
+                    voting_key_hash,
+                    operator_reward,
+                    script_payout: script_payout.1.to_vec(),
+                    inputs_hash,
+                    platform_node_id,
+                    platform_p2p_port,
+                    platform_http_port,
+                    payload_signature: payload_signature.1.to_vec(),
+                })
+            },
+        )
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        self.to_data_with_subscript_index(u64::MAX)
+    }
+
+    pub fn to_data_with_subscript_index(&self, subscript_index: u64) -> Vec<u8> {
+        self.base
+            .to_data_with_payload(subscript_index, &self.payload_data())
+    }
+
+    pub fn payout_address(&self, chain_type: ChainType) -> Option<String> {
+        address_for_chain(&self.script_payout, chain_type)
+    }
+
+    /// P2PKH address of the owner key
+    pub fn owner_address(&self, chain_type: ChainType) -> Option<String> {
+        ScriptType::PayToPubkeyHash(self.owner_key_hash).address(chain_type)
+    }
+
+    /// P2PKH address of the voting key
+    pub fn voting_address(&self, chain_type: ChainType) -> Option<String> {
+        ScriptType::PayToPubkeyHash(self.voting_key_hash).address(chain_type)
+    }
+}
+
+impl ITransaction for ProviderRegistrationTransaction {
+    fn payload_data(&self) -> Vec<u8> {
+        let mut buffer = self.payload_data_for();
+        self.payload_signature.consensus_encode(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn payload_data_for(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let offset: &mut usize = &mut 0;
+        *offset += self
+            .provider_registration_transaction_version
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        *offset += (self.provider_type as u16).consensus_encode(&mut buffer).unwrap();
+        *offset += self.provider_mode.consensus_encode(&mut buffer).unwrap();
+        *offset += self.collateral_hash.consensus_encode(&mut buffer).unwrap();
+        *offset += self.collateral_index.consensus_encode(&mut buffer).unwrap();
+        *offset += self
+            .socket_address
+            .ip_address
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        *offset += self
+            .socket_address
+            .port
+            .swap_bytes()
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        *offset += self.owner_key_hash.consensus_encode(&mut buffer).unwrap();
+        *offset += self.operator_key.consensus_encode(&mut buffer).unwrap();
+        *offset += self.voting_key_hash.consensus_encode(&mut buffer).unwrap();
+        *offset += self.operator_reward.consensus_encode(&mut buffer).unwrap();
+        *offset += self.script_payout.consensus_encode(&mut buffer).unwrap();
+        *offset += self.inputs_hash.consensus_encode(&mut buffer).unwrap();
+        if self.provider_registration_transaction_version >= BASIC_BLS_VERSION
+            && self.provider_type == MasternodeType::Evo
+        {
+            *offset += self.platform_node_id.consensus_encode(&mut buffer).unwrap();
+            *offset += self.platform_p2p_port.consensus_encode(&mut buffer).unwrap();
+            *offset += self.platform_http_port.consensus_encode(&mut buffer).unwrap();
+        }
+        buffer
+    }
+
+    fn transaction_type(&self) -> TransactionType {
+        TransactionType::ProviderRegistration
+    }
+}
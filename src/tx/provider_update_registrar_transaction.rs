@@ -0,0 +1,123 @@
+use crate::common::ChainType;
+use crate::error::{read_field, Error};
+use crate::tx::script::{address_for_chain, ScriptType};
+use crate::tx::transaction::ITransaction;
+use crate::tx::{Transaction, TransactionType};
+use byte::ctx::Endian;
+use byte::TryRead;
+use dash_spv_primitives::consensus::Encodable;
+use dash_spv_primitives::crypto::{UInt160, UInt256, UInt384, VarBytes};
+
+/// ProUpRegTx: updates the operator key, voting key and payout of a masternode
+#[derive(Debug, Clone)]
+pub struct ProviderUpdateRegistrarTransaction {
+    pub base: Transaction,
+    pub provider_update_registrar_transaction_version: u16,
+    pub provider_registration_transaction_hash: UInt256,
+    pub provider_mode: u16,
+    pub operator_key: UInt384,
+    pub voting_key_hash: UInt160,
+    pub script_payout: Vec<u8>,
+    pub inputs_hash: UInt256,
+    // Signed by the owner key
+    pub payload_signature: Vec<u8>,
+}
+
+impl<'a> TryRead<'a, Endian> for ProviderUpdateRegistrarTransaction {
+    fn try_read(bytes: &'a [u8], _endian: Endian) -> byte::Result<(Self, usize)> {
+        let offset = &mut 0;
+        let tx = Self::decode(bytes, offset)?;
+        Ok((tx, *offset))
+    }
+}
+
+impl ProviderUpdateRegistrarTransaction {
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        const STRUCTURE: &str = "ProviderUpdateRegistrarTransaction";
+        Transaction::decode_special(
+            bytes,
+            offset,
+            STRUCTURE,
+            TransactionType::ProviderUpdateRegistrar,
+            |base, bytes, offset| {
+                let provider_update_registrar_transaction_version =
+                    read_field::<u16>(bytes, offset, STRUCTURE, "version")?;
+                let provider_registration_transaction_hash = read_field::<UInt256>(
+                    bytes,
+                    offset,
+                    STRUCTURE,
+                    "provider_registration_transaction_hash",
+                )?;
+                let provider_mode = read_field::<u16>(bytes, offset, STRUCTURE, "mode")?;
+                let operator_key = read_field::<UInt384>(bytes, offset, STRUCTURE, "operator_key")?;
+                let voting_key_hash =
+                    read_field::<UInt160>(bytes, offset, STRUCTURE, "voting_key_hash")?;
+                let script_payout =
+                    read_field::<VarBytes>(bytes, offset, STRUCTURE, "script_payout")?;
+                let inputs_hash = read_field::<UInt256>(bytes, offset, STRUCTURE, "inputs_hash")?;
+                let payload_signature =
+                    read_field::<VarBytes>(bytes, offset, STRUCTURE, "payload_signature")?;
+                Ok(Self {
+                    base,
+                    provider_update_registrar_transaction_version,
+                    provider_registration_transaction_hash,
+                    provider_mode,
+                    operator_key,
+                    voting_key_hash,
+                    script_payout: script_payout.1.to_vec(),
+                    inputs_hash,
+                    payload_signature: payload_signature.1.to_vec(),
+                })
+            },
+        )
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        self.to_data_with_subscript_index(u64::MAX)
+    }
+
+    pub fn to_data_with_subscript_index(&self, subscript_index: u64) -> Vec<u8> {
+        self.base
+            .to_data_with_payload(subscript_index, &self.payload_data())
+    }
+
+    pub fn payout_address(&self, chain_type: ChainType) -> Option<String> {
+        address_for_chain(&self.script_payout, chain_type)
+    }
+
+    /// P2PKH address of the voting key
+    pub fn voting_address(&self, chain_type: ChainType) -> Option<String> {
+        ScriptType::PayToPubkeyHash(self.voting_key_hash).address(chain_type)
+    }
+}
+
+impl ITransaction for ProviderUpdateRegistrarTransaction {
+    fn payload_data(&self) -> Vec<u8> {
+        let mut buffer = self.payload_data_for();
+        self.payload_signature.consensus_encode(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn payload_data_for(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let offset: &mut usize = &mut 0;
+        *offset += self
+            .provider_update_registrar_transaction_version
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        *offset += self
+            .provider_registration_transaction_hash
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        *offset += self.provider_mode.consensus_encode(&mut buffer).unwrap();
+        *offset += self.operator_key.consensus_encode(&mut buffer).unwrap();
+        *offset += self.voting_key_hash.consensus_encode(&mut buffer).unwrap();
+        *offset += self.script_payout.consensus_encode(&mut buffer).unwrap();
+        *offset += self.inputs_hash.consensus_encode(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn transaction_type(&self) -> TransactionType {
+        TransactionType::ProviderUpdateRegistrar
+    }
+}
@@ -0,0 +1,99 @@
+use crate::error::{read_field, Error};
+use crate::tx::transaction::ITransaction;
+use crate::tx::{Transaction, TransactionType};
+use byte::ctx::Endian;
+use byte::TryRead;
+use dash_spv_primitives::consensus::Encodable;
+use dash_spv_primitives::crypto::{UInt256, UInt768};
+
+/// ProUpRevTx: the operator revokes its key, putting the masternode out of service
+#[derive(Debug, Clone)]
+pub struct ProviderUpdateRevocationTransaction {
+    pub base: Transaction,
+    pub provider_update_revocation_transaction_version: u16,
+    pub provider_registration_transaction_hash: UInt256,
+    // 0: not specified, 1: termination of service, 2: compromised keys, 3: change of keys
+    pub reason: u16,
+    pub inputs_hash: UInt256,
+    // Signed by the operator key
+    pub payload_signature: UInt768,
+}
+
+impl<'a> TryRead<'a, Endian> for ProviderUpdateRevocationTransaction {
+    fn try_read(bytes: &'a [u8], _endian: Endian) -> byte::Result<(Self, usize)> {
+        let offset = &mut 0;
+        let tx = Self::decode(bytes, offset)?;
+        Ok((tx, *offset))
+    }
+}
+
+impl ProviderUpdateRevocationTransaction {
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        const STRUCTURE: &str = "ProviderUpdateRevocationTransaction";
+        Transaction::decode_special(
+            bytes,
+            offset,
+            STRUCTURE,
+            TransactionType::ProviderUpdateRevocation,
+            |base, bytes, offset| {
+                let provider_update_revocation_transaction_version =
+                    read_field::<u16>(bytes, offset, STRUCTURE, "version")?;
+                let provider_registration_transaction_hash = read_field::<UInt256>(
+                    bytes,
+                    offset,
+                    STRUCTURE,
+                    "provider_registration_transaction_hash",
+                )?;
+                let reason = read_field::<u16>(bytes, offset, STRUCTURE, "reason")?;
+                let inputs_hash = read_field::<UInt256>(bytes, offset, STRUCTURE, "inputs_hash")?;
+                let payload_signature =
+                    read_field::<UInt768>(bytes, offset, STRUCTURE, "payload_signature")?;
+                Ok(Self {
+                    base,
+                    provider_update_revocation_transaction_version,
+                    provider_registration_transaction_hash,
+                    reason,
+                    inputs_hash,
+                    payload_signature,
+                })
+            },
+        )
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        self.to_data_with_subscript_index(u64::MAX)
+    }
+
+    pub fn to_data_with_subscript_index(&self, subscript_index: u64) -> Vec<u8> {
+        self.base
+            .to_data_with_payload(subscript_index, &self.payload_data())
+    }
+}
+
+impl ITransaction for ProviderUpdateRevocationTransaction {
+    fn payload_data(&self) -> Vec<u8> {
+        let mut buffer = self.payload_data_for();
+        self.payload_signature.consensus_encode(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn payload_data_for(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let offset: &mut usize = &mut 0;
+        *offset += self
+            .provider_update_revocation_transaction_version
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        *offset += self
+            .provider_registration_transaction_hash
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        *offset += self.reason.consensus_encode(&mut buffer).unwrap();
+        *offset += self.inputs_hash.consensus_encode(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn transaction_type(&self) -> TransactionType {
+        TransactionType::ProviderUpdateRevocation
+    }
+}
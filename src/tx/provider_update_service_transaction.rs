@@ -0,0 +1,157 @@
+use crate::common::{ChainType, MasternodeType, SocketAddress};
+use crate::error::{read_field, Error};
+use crate::masternode::masternode_entry::BASIC_BLS_VERSION;
+use crate::tx::script::address_for_chain;
+use crate::tx::transaction::ITransaction;
+use crate::tx::{Transaction, TransactionType};
+use byte::ctx::Endian;
+use byte::TryRead;
+use dash_spv_primitives::consensus::Encodable;
+use dash_spv_primitives::crypto::{UInt128, UInt160, UInt256, UInt768, VarBytes};
+
+/// ProUpServTx: updates the service address and operator payout of a masternode
+#[derive(Debug, Clone)]
+pub struct ProviderUpdateServiceTransaction {
+    pub base: Transaction,
+    pub provider_update_service_transaction_version: u16,
+    // Sent from version 2 on
+    pub provider_type: MasternodeType,
+    pub provider_registration_transaction_hash: UInt256,
+    pub socket_address: SocketAddress,
+    pub script_operator_payout: Vec<u8>,
+    pub inputs_hash: UInt256,
+    // Evonodes only
+    pub platform_node_id: UInt160,
+    pub platform_p2p_port: u16,
+    pub platform_http_port: u16,
+    // Signed by the operator key
+    pub payload_signature: UInt768,
+}
+
+impl<'a> TryRead<'a, Endian> for ProviderUpdateServiceTransaction {
+    fn try_read(bytes: &'a [u8], _endian: Endian) -> byte::Result<(Self, usize)> {
+        let offset = &mut 0;
+        let tx = Self::decode(bytes, offset)?;
+        Ok((tx, *offset))
+    }
+}
+
+impl ProviderUpdateServiceTransaction {
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        const STRUCTURE: &str = "ProviderUpdateServiceTransaction";
+        Transaction::decode_special(
+            bytes,
+            offset,
+            STRUCTURE,
+            TransactionType::ProviderUpdateService,
+            |base, bytes, offset| {
+                let provider_update_service_transaction_version =
+                    read_field::<u16>(bytes, offset, STRUCTURE, "version")?;
+                let provider_type =
+                    if provider_update_service_transaction_version >= BASIC_BLS_VERSION {
+                        read_field::<MasternodeType>(bytes, offset, STRUCTURE, "type")?
+                    } else {
+                        MasternodeType::Regular
+                    };
+                let provider_registration_transaction_hash = read_field::<UInt256>(
+                    bytes,
+                    offset,
+                    STRUCTURE,
+                    "provider_registration_transaction_hash",
+                )?;
+                let ip_address = read_field::<UInt128>(bytes, offset, STRUCTURE, "ip_address")?;
+                let port = read_field::<u16>(bytes, offset, STRUCTURE, "port")?.swap_bytes();
+                let script_operator_payout =
+                    read_field::<VarBytes>(bytes, offset, STRUCTURE, "script_operator_payout")?;
+                let inputs_hash = read_field::<UInt256>(bytes, offset, STRUCTURE, "inputs_hash")?;
+                let mut platform_node_id = UInt160::default();
+                let mut platform_p2p_port = 0;
+                let mut platform_http_port = 0;
+                if provider_type == MasternodeType::Evo {
+                    platform_node_id =
+                        read_field::<UInt160>(bytes, offset, STRUCTURE, "platform_node_id")?;
+                    platform_p2p_port =
+                        read_field::<u16>(bytes, offset, STRUCTURE, "platform_p2p_port")?;
+                    platform_http_port =
+                        read_field::<u16>(bytes, offset, STRUCTURE, "platform_http_port")?;
+                }
+                let payload_signature =
+                    read_field::<UInt768>(bytes, offset, STRUCTURE, "payload_signature")?;
+                Ok(Self {
+                    base,
+                    provider_update_service_transaction_version,
+                    provider_type,
+                    provider_registration_transaction_hash,
+                    socket_address: SocketAddress { ip_address, port },
+                    script_operator_payout: script_operator_payout.1.to_vec(),
+                    inputs_hash,
+                    platform_node_id,
+                    platform_p2p_port,
+                    platform_http_port,
+                    payload_signature,
+                })
+            },
+        )
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        self.to_data_with_subscript_index(u64::MAX)
+    }
+
+    pub fn to_data_with_subscript_index(&self, subscript_index: u64) -> Vec<u8> {
+        self.base
+            .to_data_with_payload(subscript_index, &self.payload_data())
+    }
+
+    /// None when the operator reward isn't paid out or not to a P2PKH or P2SH script
+    pub fn operator_payout_address(&self, chain_type: ChainType) -> Option<String> {
+        address_for_chain(&self.script_operator_payout, chain_type)
+    }
+}
+
+impl ITransaction for ProviderUpdateServiceTransaction {
+    fn payload_data(&self) -> Vec<u8> {
+        let mut buffer = self.payload_data_for();
+        self.payload_signature.consensus_encode(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn payload_data_for(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let offset: &mut usize = &mut 0;
+        *offset += self
+            .provider_update_service_transaction_version
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        if self.provider_update_service_transaction_version >= BASIC_BLS_VERSION {
+            *offset += (self.provider_type as u16).consensus_encode(&mut buffer).unwrap();
+        }
+        *offset += self
+            .provider_registration_transaction_hash
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        *offset += self
+            .socket_address
+            .ip_address
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        *offset += self
+            .socket_address
+            .port
+            .swap_bytes()
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        *offset += self.script_operator_payout.consensus_encode(&mut buffer).unwrap();
+        *offset += self.inputs_hash.consensus_encode(&mut buffer).unwrap();
+        if self.provider_type == MasternodeType::Evo {
+            *offset += self.platform_node_id.consensus_encode(&mut buffer).unwrap();
+            *offset += self.platform_p2p_port.consensus_encode(&mut buffer).unwrap();
+            *offset += self.platform_http_port.consensus_encode(&mut buffer).unwrap();
+        }
+        buffer
+    }
+
+    fn transaction_type(&self) -> TransactionType {
+        TransactionType::ProviderUpdateService
+    }
+}
@@ -0,0 +1,83 @@
+use crate::error::{read_field, Error};
+use crate::masternode::LLMQEntry;
+use crate::tx::transaction::ITransaction;
+use crate::tx::{Transaction, TransactionType};
+use byte::ctx::Endian;
+use byte::TryRead;
+use dash_spv_primitives::consensus::Encodable;
+
+/// QcTx: final commitment of a quorum DKG session, mined without inputs nor outputs
+#[derive(Debug, Clone)]
+pub struct QuorumCommitmentTransaction {
+    pub base: Transaction,
+    pub quorum_commitment_transaction_version: u16,
+    pub quorum_commitment_height: u32,
+    pub quorum_entry: LLMQEntry,
+}
+
+impl<'a> TryRead<'a, Endian> for QuorumCommitmentTransaction {
+    fn try_read(bytes: &'a [u8], _endian: Endian) -> byte::Result<(Self, usize)> {
+        let offset = &mut 0;
+        let tx = Self::decode(bytes, offset)?;
+        Ok((tx, *offset))
+    }
+}
+
+impl QuorumCommitmentTransaction {
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        const STRUCTURE: &str = "QuorumCommitmentTransaction";
+        Transaction::decode_special(
+            bytes,
+            offset,
+            STRUCTURE,
+            TransactionType::QuorumCommitment,
+            |base, bytes, offset| {
+                let quorum_commitment_transaction_version =
+                    read_field::<u16>(bytes, offset, STRUCTURE, "version")?;
+                let quorum_commitment_height =
+                    read_field::<u32>(bytes, offset, STRUCTURE, "quorum_commitment_height")?;
+                let quorum_entry = LLMQEntry::decode(bytes, offset)?;
+                Ok(Self {
+                    base,
+                    quorum_commitment_transaction_version,
+                    quorum_commitment_height,
+                    quorum_entry,
+                })
+            },
+        )
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        self.to_data_with_subscript_index(u64::MAX)
+    }
+
+    pub fn to_data_with_subscript_index(&self, subscript_index: u64) -> Vec<u8> {
+        self.base
+            .to_data_with_payload(subscript_index, &self.payload_data())
+    }
+}
+
+impl ITransaction for QuorumCommitmentTransaction {
+    fn payload_data(&self) -> Vec<u8> {
+        self.payload_data_for()
+    }
+
+    fn payload_data_for(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let offset: &mut usize = &mut 0;
+        *offset += self
+            .quorum_commitment_transaction_version
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        *offset += self
+            .quorum_commitment_height
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        buffer.extend(self.quorum_entry.to_data());
+        buffer
+    }
+
+    fn transaction_type(&self) -> TransactionType {
+        TransactionType::QuorumCommitment
+    }
+}
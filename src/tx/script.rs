@@ -0,0 +1,219 @@
+use crate::common::base58;
+use crate::common::ChainType;
+use dash_spv_primitives::crypto::UInt160;
+
+// Marks the start of the script part covered by signatures, removed from the script code
+pub const OP_CODESEPARATOR: u8 = 0xab;
+pub const OP_PUSHDATA1: u8 = 0x4c;
+pub const OP_PUSHDATA2: u8 = 0x4d;
+pub const OP_PUSHDATA4: u8 = 0x4e;
+pub const OP_RETURN: u8 = 0x6a;
+pub const OP_DUP: u8 = 0x76;
+pub const OP_EQUAL: u8 = 0x87;
+pub const OP_EQUALVERIFY: u8 = 0x88;
+pub const OP_HASH160: u8 = 0xa9;
+pub const OP_CHECKSIG: u8 = 0xac;
+
+/// Standard output script templates
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ScriptType {
+    // OP_DUP OP_HASH160 <pubkey hash> OP_EQUALVERIFY OP_CHECKSIG
+    PayToPubkeyHash(UInt160),
+    // OP_HASH160 <script hash> OP_EQUAL
+    PayToScriptHash(UInt160),
+    // <compressed or uncompressed pubkey> OP_CHECKSIG
+    PayToPubkey(Vec<u8>),
+    // OP_RETURN <data>: provably unspendable, used for burns and asset locks
+    OpReturn(Vec<u8>),
+    Unknown,
+}
+
+impl ScriptType {
+    pub fn from_script(script: &[u8]) -> Self {
+        match script {
+            [OP_DUP, OP_HASH160, 20, hash @ .., OP_EQUALVERIFY, OP_CHECKSIG]
+                if hash.len() == 20 =>
+            {
+                ScriptType::PayToPubkeyHash(UInt160(hash.try_into().unwrap()))
+            }
+            [OP_HASH160, 20, hash @ .., OP_EQUAL] if hash.len() == 20 => {
+                ScriptType::PayToScriptHash(UInt160(hash.try_into().unwrap()))
+            }
+            [len @ (33 | 65), pubkey @ .., OP_CHECKSIG] if pubkey.len() == *len as usize => {
+                ScriptType::PayToPubkey(pubkey.to_vec())
+            }
+            [OP_RETURN, data @ ..] => ScriptType::OpReturn(data.to_vec()),
+            _ => ScriptType::Unknown,
+        }
+    }
+
+    /// Output script of the template
+    pub fn script(&self) -> Option<Vec<u8>> {
+        match self {
+            ScriptType::PayToPubkeyHash(hash) => {
+                let mut script = vec![OP_DUP, OP_HASH160, 20];
+                script.extend_from_slice(&hash.0);
+                script.extend([OP_EQUALVERIFY, OP_CHECKSIG]);
+                Some(script)
+            }
+            ScriptType::PayToScriptHash(hash) => {
+                let mut script = vec![OP_HASH160, 20];
+                script.extend_from_slice(&hash.0);
+                script.push(OP_EQUAL);
+                Some(script)
+            }
+            ScriptType::PayToPubkey(pubkey) => {
+                let mut script = vec![pubkey.len() as u8];
+                script.extend_from_slice(pubkey);
+                script.push(OP_CHECKSIG);
+                Some(script)
+            }
+            ScriptType::OpReturn(data) => {
+                let mut script = vec![OP_RETURN];
+                script.extend_from_slice(data);
+                Some(script)
+            }
+            ScriptType::Unknown => None,
+        }
+    }
+
+    /// Base58Check address of P2PKH and P2SH scripts on the chain
+    pub fn address(&self, chain_type: ChainType) -> Option<String> {
+        let (prefix, hash) = match self {
+            ScriptType::PayToPubkeyHash(hash) => (chain_type.pubkey_address_prefix(), hash),
+            ScriptType::PayToScriptHash(hash) => (chain_type.script_address_prefix(), hash),
+            _ => return None,
+        };
+        let mut data = vec![prefix];
+        data.extend_from_slice(&hash.0);
+        Some(base58::encode_check(&data))
+    }
+
+    /// P2PKH or P2SH template of an address of the chain
+    pub fn from_address(address: &str, chain_type: ChainType) -> Option<Self> {
+        let data = base58::decode_check(address).ok()?;
+        match data.split_first() {
+            Some((prefix, hash)) if hash.len() == 20 => {
+                let hash = UInt160(hash.try_into().unwrap());
+                if *prefix == chain_type.pubkey_address_prefix() {
+                    Some(ScriptType::PayToPubkeyHash(hash))
+                } else if *prefix == chain_type.script_address_prefix() {
+                    Some(ScriptType::PayToScriptHash(hash))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Address an output script pays to on the chain, if it is a P2PKH or P2SH one
+pub fn address_for_chain(script: &[u8], chain_type: ChainType) -> Option<String> {
+    ScriptType::from_script(script).address(chain_type)
+}
+
+/// Output script paying to an address of the chain
+pub fn script_for_address(address: &str, chain_type: ChainType) -> Option<Vec<u8>> {
+    ScriptType::from_address(address, chain_type)?.script()
+}
+
+/// Length of the script element starting at position: the opcode and the data it pushes.
+/// None when the pushed data goes past the end of the script
+pub fn element_length(script: &[u8], position: usize) -> Option<usize> {
+    let opcode = *script.get(position)?;
+    let (header, data) = match opcode {
+        0x01..=0x4b => (1, opcode as usize),
+        OP_PUSHDATA1 => (2, *script.get(position + 1)? as usize),
+        OP_PUSHDATA2 => {
+            let len = script.get(position + 1..position + 3)?;
+            (3, u16::from_le_bytes([len[0], len[1]]) as usize)
+        }
+        OP_PUSHDATA4 => {
+            let len = script.get(position + 1..position + 5)?;
+            (5, u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize)
+        }
+        _ => (1, 0),
+    };
+    let length = header + data;
+    if position + length > script.len() {
+        None
+    } else {
+        Some(length)
+    }
+}
+
+/// Script code without its OP_CODESEPARATOR opcodes, as signed by legacy signature hashes.
+/// Data pushes are kept as is and a truncated trailing push ends the script unchanged
+pub fn without_code_separators(script: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(script.len());
+    let mut position = 0;
+    while position < script.len() {
+        match element_length(script, position) {
+            Some(length) => {
+                if script[position] != OP_CODESEPARATOR {
+                    result.extend_from_slice(&script[position..position + length]);
+                }
+                position += length;
+            }
+            None => {
+                result.extend_from_slice(&script[position..]);
+                break;
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::ChainType;
+    use crate::tx::script::{
+        address_for_chain, script_for_address, without_code_separators, ScriptType,
+    };
+    use dash_spv_primitives::crypto::UInt160;
+
+    #[test]
+    fn standard_scripts_and_addresses() {
+        let hash = UInt160([7u8; 20]);
+        let p2pkh = ScriptType::PayToPubkeyHash(hash).script().unwrap();
+        let p2sh = ScriptType::PayToScriptHash(hash).script().unwrap();
+        assert_eq!(p2pkh.len(), 25);
+        assert_eq!(p2sh.len(), 23);
+        assert_eq!(ScriptType::from_script(&p2pkh), ScriptType::PayToPubkeyHash(hash));
+        assert_eq!(ScriptType::from_script(&p2sh), ScriptType::PayToScriptHash(hash));
+        let mut p2pk = vec![33u8];
+        p2pk.extend([2u8; 33]);
+        p2pk.push(0xac);
+        assert_eq!(ScriptType::from_script(&p2pk), ScriptType::PayToPubkey(vec![2u8; 33]));
+        assert_eq!(
+            ScriptType::from_script(&[0x6a, 0x02, 0x01, 0x02]),
+            ScriptType::OpReturn(vec![0x02, 0x01, 0x02])
+        );
+        assert_eq!(ScriptType::from_script(&p2pkh[..24]), ScriptType::Unknown);
+        assert_eq!(address_for_chain(&p2pk, ChainType::MainNet), None);
+        for (chain_type, pubkey_prefix, script_prefix) in
+            [(ChainType::MainNet, 'X', '7'), (ChainType::TestNet, 'y', '8')]
+        {
+            let pubkey_address = address_for_chain(&p2pkh, chain_type).unwrap();
+            let script_address = address_for_chain(&p2sh, chain_type).unwrap();
+            assert!(pubkey_address.starts_with(pubkey_prefix));
+            assert!(script_address.starts_with(script_prefix));
+            assert_eq!(script_for_address(&pubkey_address, chain_type), Some(p2pkh.clone()));
+            assert_eq!(script_for_address(&script_address, chain_type), Some(p2sh.clone()));
+        }
+        // addresses of another chain are rejected
+        let testnet_address = address_for_chain(&p2pkh, ChainType::TestNet).unwrap();
+        assert_eq!(script_for_address(&testnet_address, ChainType::MainNet), None);
+    }
+
+    #[test]
+    fn code_separators_are_removed_outside_pushes() {
+        assert_eq!(
+            without_code_separators(&[0xab, 0x02, 0xab, 0xab, 0xab, 0x4c, 0x01, 0xab]),
+            vec![0x02, 0xab, 0xab, 0x4c, 0x01, 0xab]
+        );
+        // a truncated push is kept as is
+        assert_eq!(without_code_separators(&[0xab, 0x05, 0xab]), vec![0x05, 0xab]);
+    }
+}
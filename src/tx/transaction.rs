@@ -1,5 +1,8 @@
 use byte::ctx::Endian;
-use byte::{BytesExt, TryRead, LE};
+use crate::common::ChainType;
+use crate::error::{read_field, Error};
+use crate::tx::script::{without_code_separators, ScriptType};
+use byte::TryRead;
 use dash_spv_primitives::consensus::encode::VarInt;
 use dash_spv_primitives::consensus::Encodable;
 use dash_spv_primitives::crypto::{UInt256, VarBytes};
@@ -10,22 +13,26 @@ use dash_spv_primitives::hashes::{sha256d, Hash};
 pub const TX_UNCONFIRMED: i32 = i32::MAX;
 
 pub static SIGHASH_ALL: u32 = 1;
+pub static SIGHASH_NONE: u32 = 2;
+pub static SIGHASH_SINGLE: u32 = 3;
+pub static SIGHASH_ANYONECANPAY: u32 = 0x80;
 
-#[repr(C)]
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TransactionType {
-    Classic = 0,
-    ProviderRegistration = 1,
-    ProviderUpdateService = 2,
-    ProviderUpdateRegistrar = 3,
-    ProviderUpdateRevocation = 4,
-    Coinbase = 5,
-    QuorumCommitment = 6,
-    SubscriptionRegistration = 8,
-    SubscriptionTopUp = 9,
-    SubscriptionResetKey = 10,
-    SubscriptionCloseAccount = 11,
-    Transition = 12,
+    Classic,
+    ProviderRegistration,
+    ProviderUpdateService,
+    ProviderUpdateRegistrar,
+    ProviderUpdateRevocation,
+    Coinbase,
+    QuorumCommitment,
+    SubscriptionRegistration,
+    SubscriptionTopUp,
+    SubscriptionResetKey,
+    SubscriptionCloseAccount,
+    Transition,
+    // Any other nType, kept so that the transaction is serialized back as it was received
+    Unknown(u16),
 }
 
 impl From<u16> for TransactionType {
@@ -43,23 +50,38 @@ impl From<u16> for TransactionType {
             0x000A => TransactionType::SubscriptionResetKey,
             0x000B => TransactionType::SubscriptionCloseAccount,
             0x000C => TransactionType::Transition,
-            _ => TransactionType::Classic,
+            _ => TransactionType::Unknown(orig),
         }
     }
 }
 
 impl From<TransactionType> for u16 {
     fn from(value: TransactionType) -> Self {
-        value as u16
+        value.raw_value()
     }
 }
 
 impl TransactionType {
     fn raw_value(&self) -> u16 {
-        *self as u16
+        match self {
+            TransactionType::Classic => 0x0000,
+            TransactionType::ProviderRegistration => 0x0001,
+            TransactionType::ProviderUpdateService => 0x0002,
+            TransactionType::ProviderUpdateRegistrar => 0x0003,
+            TransactionType::ProviderUpdateRevocation => 0x0004,
+            TransactionType::Coinbase => 0x0005,
+            TransactionType::QuorumCommitment => 0x0006,
+            TransactionType::SubscriptionRegistration => 0x0008,
+            TransactionType::SubscriptionTopUp => 0x0009,
+            TransactionType::SubscriptionResetKey => 0x000A,
+            TransactionType::SubscriptionCloseAccount => 0x000B,
+            TransactionType::Transition => 0x000C,
+            TransactionType::Unknown(raw) => *raw,
+        }
     }
     pub fn requires_inputs(&self) -> bool {
-        true
+        // quorum commitments are created by the miner without inputs nor outputs
+        *self != TransactionType::QuorumCommitment
     }
 }
 
@@ -67,9 +89,13 @@ impl TransactionType {
 pub struct TransactionInput {
     pub input_hash: UInt256,
     pub index: u32,
+    // Script of the spent output (scriptPubKey), unknown until attached after decoding
     pub script: Option<Vec<u8>>,
+    // Input script as serialized (scriptSig)
     pub signature: Option<Vec<u8>>,
     pub sequence: u32,
+    // Amount of the spent output, unknown until attached after decoding
+    pub amount: Option<u64>,
 }
 
 impl std::fmt::Debug for TransactionInput {
@@ -90,6 +116,7 @@ impl std::fmt::Debug for TransactionInput {
                     .to_hex(),
             )
             .field("sequence", &self.sequence)
+            .field("amount", &self.amount)
             .finish()
     }
 }
@@ -97,21 +124,31 @@ impl std::fmt::Debug for TransactionInput {
 impl<'a> TryRead<'a, Endian> for TransactionInput {
     fn try_read(bytes: &'a [u8], _endian: Endian) -> byte::Result<(Self, usize)> {
         let offset = &mut 0;
-        let input_hash = bytes.read_with::<UInt256>(offset, LE)?;
-        let index = bytes.read_with::<u32>(offset, LE)?;
-        let signature = match bytes.read_with::<VarBytes>(offset, LE) {
-            Ok(data) => Some(data.1.to_vec()),
-            Err(_err) => None,
-        };
-        let sequence = bytes.read_with::<u32>(offset, LE)?;
-        let input = TransactionInput {
+        let input = Self::decode(bytes, offset)?;
+        Ok((input, *offset))
+    }
+}
+
+impl TransactionInput {
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        let input_hash = read_field::<UInt256>(bytes, offset, "TransactionInput", "input_hash")?;
+        let index = read_field::<u32>(bytes, offset, "TransactionInput", "index")?;
+        let signature = read_field::<VarBytes>(bytes, offset, "TransactionInput", "signature")?;
+        let sequence = read_field::<u32>(bytes, offset, "TransactionInput", "sequence")?;
+        Ok(TransactionInput {
             input_hash,
             index,
             script: None,
-            signature,
+            signature: Some(signature.1.to_vec()),
             sequence,
-        };
-        Ok((input, *offset))
+            amount: None,
+        })
+    }
+
+    /// Remembers the output spent by this input, which is not part of its serialization
+    pub fn attach_prevout(&mut self, prevout: &TransactionOutput) {
+        self.script = prevout.script.clone();
+        self.amount = Some(prevout.amount);
     }
 }
 
@@ -141,17 +178,36 @@ impl std::fmt::Debug for TransactionOutput {
 impl<'a> TryRead<'a, Endian> for TransactionOutput {
     fn try_read(bytes: &'a [u8], _endian: Endian) -> byte::Result<(Self, usize)> {
         let offset = &mut 0;
-        let amount = bytes.read_with::<u64>(offset, LE)?;
-        let script = match bytes.read_with::<VarBytes>(offset, LE) {
-            Ok(data) => Some(data.1.to_vec()),
-            Err(_err) => None,
-        };
-        let output = TransactionOutput {
+        let output = Self::decode(bytes, offset)?;
+        Ok((output, *offset))
+    }
+}
+
+impl TransactionOutput {
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        let amount = read_field::<u64>(bytes, offset, "TransactionOutput", "amount")?;
+        let script = read_field::<VarBytes>(bytes, offset, "TransactionOutput", "script")?;
+        Ok(TransactionOutput {
             amount,
-            script,
+            script: Some(script.1.to_vec()),
             address: None,
-        };
-        Ok((output, *offset))
+        })
+    }
+
+    pub fn script_type(&self) -> ScriptType {
+        ScriptType::from_script(self.script.as_deref().unwrap_or_default())
+    }
+
+    /// Base58Check address of the recipient on the chain for P2PKH and P2SH outputs
+    pub fn address_for_chain(&self, chain_type: ChainType) -> Option<String> {
+        self.script_type().address(chain_type)
+    }
+
+    /// Decoding doesn't know the chain, so the address is filled in afterwards
+    pub fn update_address(&mut self, chain_type: ChainType) {
+        self.address = self
+            .address_for_chain(chain_type)
+            .map(|address| address.into_bytes());
     }
 }
 
@@ -169,6 +225,8 @@ pub struct Transaction {
     pub version: u16,
     pub tx_hash: Option<UInt256>,
     pub tx_type: TransactionType,
+    // Raw extraPayload of special transactions (version 3 and non-classic type)
+    pub extra_payload: Option<Vec<u8>>,
     pub payload_offset: usize,
     pub block_height: u32,
 }
@@ -186,9 +244,137 @@ impl Transaction {
             &self.inputs,
             &self.outputs,
             self.lock_time,
+            self.extra_payload.as_deref(),
         )
     }
 
+    /// Digest signed by input input_index for script_code (the previous output script)
+    /// and sighash_type, following the legacy SignatureHash of Dash Core, where the
+    /// extraPayload of special transactions is part of the signed data
+    pub fn signature_hash(
+        &self,
+        input_index: usize,
+        script_code: &[u8],
+        sighash_type: u32,
+    ) -> UInt256 {
+        // Dash Core returns 1 instead of failing for these
+        let mut one = [0u8; 32];
+        one[0] = 1;
+        let base_type = sighash_type & 0x1f;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        if input_index >= self.inputs.len()
+            || (base_type == SIGHASH_SINGLE && input_index >= self.outputs.len())
+        {
+            return UInt256(one);
+        }
+        let mut buffer: Vec<u8> = Vec::new();
+        let offset: &mut usize = &mut 0;
+        *offset += self.version.consensus_encode(&mut buffer).unwrap();
+        *offset += self.tx_type.raw_value().consensus_encode(&mut buffer).unwrap();
+        let signed_inputs = if anyone_can_pay {
+            input_index..input_index + 1
+        } else {
+            0..self.inputs.len()
+        };
+        *offset += VarInt(signed_inputs.len() as u64)
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        for i in signed_inputs {
+            let input = &self.inputs[i];
+            *offset += input.input_hash.consensus_encode(&mut buffer).unwrap();
+            *offset += input.index.consensus_encode(&mut buffer).unwrap();
+            if i == input_index {
+                *offset += without_code_separators(script_code)
+                    .consensus_encode(&mut buffer)
+                    .unwrap();
+            } else {
+                *offset += VarInt(0_u64).consensus_encode(&mut buffer).unwrap();
+            }
+            // other inputs can be updated when the outputs are not all signed
+            let sequence = if i != input_index
+                && (base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE)
+            {
+                0
+            } else {
+                input.sequence
+            };
+            *offset += sequence.consensus_encode(&mut buffer).unwrap();
+        }
+        let signed_outputs = match base_type {
+            b if b == SIGHASH_NONE => 0,
+            b if b == SIGHASH_SINGLE => input_index + 1,
+            _ => self.outputs.len(),
+        };
+        *offset += VarInt(signed_outputs as u64)
+            .consensus_encode(&mut buffer)
+            .unwrap();
+        for (i, output) in self.outputs.iter().take(signed_outputs).enumerate() {
+            if base_type == SIGHASH_SINGLE && i != input_index {
+                // blank output: -1 amount and empty script
+                *offset += u64::MAX.consensus_encode(&mut buffer).unwrap();
+                *offset += VarInt(0_u64).consensus_encode(&mut buffer).unwrap();
+            } else {
+                *offset += output.amount.consensus_encode(&mut buffer).unwrap();
+                *offset += output
+                    .script
+                    .clone()
+                    .unwrap_or_default()
+                    .consensus_encode(&mut buffer)
+                    .unwrap();
+            }
+        }
+        *offset += self.lock_time.consensus_encode(&mut buffer).unwrap();
+        if let Some(payload) = &self.extra_payload {
+            *offset += payload.consensus_encode(&mut buffer).unwrap();
+        }
+        *offset += sighash_type.consensus_encode(&mut buffer).unwrap();
+        UInt256(sha256d::Hash::hash(&buffer).into_inner())
+    }
+
+    /// Attaches the output spent by input input_index, false if there is no such input
+    pub fn attach_prevout(&mut self, input_index: usize, prevout: &TransactionOutput) -> bool {
+        match self.inputs.get_mut(input_index) {
+            Some(input) => {
+                input.attach_prevout(prevout);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Signature hash of input input_index for its attached prevout script
+    pub fn input_signature_hash(&self, input_index: usize, sighash_type: u32) -> Option<UInt256> {
+        let script = self.inputs.get(input_index)?.script.as_ref()?;
+        Some(self.signature_hash(input_index, script, sighash_type))
+    }
+
+    /// Inputs amount minus outputs amount, once every prevout amount is attached
+    pub fn fee(&self) -> Option<u64> {
+        let inputs_amount = self
+            .inputs
+            .iter()
+            .try_fold(0u64, |sum, input| sum.checked_add(input.amount?))?;
+        let outputs_amount = self
+            .outputs
+            .iter()
+            .try_fold(0u64, |sum, output| sum.checked_add(output.amount))?;
+        inputs_amount.checked_sub(outputs_amount)
+    }
+
+    /// Fills in the address of every output for the chain
+    pub fn update_output_addresses(&mut self, chain_type: ChainType) {
+        self.outputs
+            .iter_mut()
+            .for_each(|output| output.update_address(chain_type));
+    }
+
+    /// Whether the transaction carries an extraPayload.
+    /// nVersion is signed in Dash Core, so versions from 0x8000 on are never special
+    pub fn is_special(version: u16, tx_type: TransactionType) -> bool {
+        version as i16 >= 3 && tx_type != TransactionType::Classic
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn data_with_subscript_index_static(
         subscript_index: u64,
         version: u16,
@@ -196,6 +382,7 @@ impl Transaction {
         inputs: &[TransactionInput],
         outputs: &[TransactionOutput],
         lock_time: u32,
+        extra_payload: Option<&[u8]>,
     ) -> Vec<u8> {
         let mut buffer: Vec<u8> = Vec::new();
         let offset: &mut usize = &mut 0;
@@ -243,50 +430,449 @@ impl Transaction {
             }
         });
         *offset += lock_time.consensus_encode(&mut buffer).unwrap();
+        if let Some(payload) = extra_payload {
+            *offset += VarInt(payload.len() as u64)
+                .consensus_encode(&mut buffer)
+                .unwrap();
+            buffer.extend_from_slice(payload);
+            *offset += payload.len();
+        }
         if subscript_index != u64::MAX {
             *offset += SIGHASH_ALL.consensus_encode(&mut buffer).unwrap();
         }
         buffer
     }
 }
+impl Transaction {
+    /// Decodes a special transaction of type tx_type: the whole transaction, then its
+    /// extraPayload with decode_payload, which receives the classic part and has to consume
+    /// the payload exactly. Offsets stay absolute and reading past the payload fails
+    pub(crate) fn decode_special<T>(
+        bytes: &[u8],
+        offset: &mut usize,
+        structure: &'static str,
+        tx_type: TransactionType,
+        decode_payload: impl FnOnce(Transaction, &[u8], &mut usize) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let start = *offset;
+        let base = Transaction::decode(bytes, offset)?;
+        if base.tx_type != tx_type {
+            return Err(Error::BadInput {
+                structure,
+                field: "tx_type",
+                offset: start + 2,
+                reason: "unexpected transaction type",
+            });
+        }
+        let payload_end = *offset;
+        let payload_start = match &base.extra_payload {
+            Some(payload) => payload_end - payload.len(),
+            None => {
+                return Err(Error::BadInput {
+                    structure,
+                    field: "extra_payload",
+                    offset: payload_end,
+                    reason: "special transaction has no payload",
+                })
+            }
+        };
+        let mut payload_offset = payload_start;
+        let tx = decode_payload(base, &bytes[..payload_end], &mut payload_offset)?;
+        if payload_offset != payload_end {
+            return Err(Error::BadInput {
+                structure,
+                field: "extra_payload",
+                offset: payload_start,
+                reason: "payload doesn't match its declared size",
+            });
+        }
+        Ok(tx)
+    }
+
+    /// Serialization of a special transaction whose extraPayload is payload
+    pub(crate) fn to_data_with_payload(&self, subscript_index: u64, payload: &[u8]) -> Vec<u8> {
+        Self::data_with_subscript_index_static(
+            subscript_index,
+            self.version,
+            self.tx_type,
+            &self.inputs,
+            &self.outputs,
+            self.lock_time,
+            Some(payload),
+        )
+    }
+}
+
 impl<'a> TryRead<'a, Endian> for Transaction {
-    fn try_read(bytes: &'a [u8], endian: Endian) -> byte::Result<(Self, usize)> {
+    fn try_read(bytes: &'a [u8], _endian: Endian) -> byte::Result<(Self, usize)> {
         let offset = &mut 0;
-        let version = bytes.read_with::<u16>(offset, endian)?;
-        let tx_type_uint = bytes.read_with::<u16>(offset, endian)?;
+        let tx = Self::decode(bytes, offset)?;
+        Ok((tx, *offset))
+    }
+}
+
+impl Transaction {
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        let start = *offset;
+        let version = read_field::<u16>(bytes, offset, "Transaction", "version")?;
+        let tx_type_offset = *offset;
+        let tx_type_uint = read_field::<u16>(bytes, offset, "Transaction", "tx_type")?;
         let tx_type = TransactionType::from(tx_type_uint);
-        let count_var = bytes.read_with::<VarInt>(offset, endian)?;
-        let count = count_var.0;
+        let count = read_field::<VarInt>(bytes, offset, "Transaction", "inputs_count")?.0;
         // at least one input is required
         if count == 0 && tx_type.requires_inputs() {
-            return Err(byte::Error::Incomplete);
+            return Err(Error::BadInput {
+                structure: "Transaction",
+                field: "inputs_count",
+                offset: tx_type_offset + 2,
+                reason: "transaction has no inputs",
+            });
         }
         let mut inputs: Vec<TransactionInput> = Vec::new();
         for _i in 0..count {
-            inputs.push(bytes.read_with::<TransactionInput>(offset, endian)?);
+            inputs.push(TransactionInput::decode(bytes, offset)?);
         }
         let mut outputs: Vec<TransactionOutput> = Vec::new();
-        let count_var = bytes.read_with::<VarInt>(offset, endian)?;
-        let count = count_var.0;
+        let count = read_field::<VarInt>(bytes, offset, "Transaction", "outputs_count")?.0;
         for _i in 0..count {
-            outputs.push(bytes.read_with::<TransactionOutput>(offset, endian)?);
+            outputs.push(TransactionOutput::decode(bytes, offset)?);
         }
-        let lock_time = bytes.read_with::<u32>(offset, endian)?;
-        let mut tx = Self {
+        let lock_time = read_field::<u32>(bytes, offset, "Transaction", "lock_time")?;
+        let payload_offset = *offset - start;
+        let extra_payload = if Self::is_special(version, tx_type) {
+            let payload = read_field::<VarBytes>(bytes, offset, "Transaction", "extra_payload")?;
+            Some(payload.1.to_vec())
+        } else {
+            None
+        };
+        let tx_hash = UInt256(sha256d::Hash::hash(&bytes[start..*offset]).into_inner());
+        Ok(Self {
             inputs,
             outputs,
-            tx_hash: None,
+            tx_hash: Some(tx_hash),
             version,
             tx_type,
             lock_time,
-            payload_offset: *offset,
+            extra_payload,
+            payload_offset,
             block_height: TX_UNCONFIRMED as u32,
-        };
-        tx.tx_hash = if tx_type == TransactionType::Classic {
-            Some(UInt256(sha256d::Hash::hash(&tx.to_data()).into_inner()))
-        } else {
-            None
-        };
-        Ok((tx, *offset))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures;
+    use crate::tx::transaction::{
+        SIGHASH_ALL, SIGHASH_ANYONECANPAY, SIGHASH_NONE, SIGHASH_SINGLE,
+    };
+    use crate::tx::{Transaction, TransactionInput, TransactionOutput, TransactionType};
+    use dash_spv_primitives::crypto::byte_util::Reversable;
+    use dash_spv_primitives::crypto::UInt256;
+    use dash_spv_primitives::hashes::hex::FromHex;
+    use dash_spv_primitives::hashes::{sha256d, Hash};
+
+    // Vectors of Bitcoin Core's sighash.json: raw transaction, scriptCode, input index, hash type
+    // and expected hash in display order. Only transactions with a negative 16-bit version are
+    // kept: Dash Core never reads an extraPayload for them, so they're signed as in Bitcoin
+    const SIGHASH_VECTORS: [(&str, &str, usize, i32, &str); 12] = [
+        (
+            "4db591ab018adcef5f4f3f2060e41f7829ce3a07ea41d681e8cb70a0e37685561e4767ac3b0000000005000052acabd280e63601ae6ef20000000000036a636326c908f7",
+            "ac6a51526300630052",
+            0,
+            862877446,
+            "355ccaf30697c9c5b966e619a554d3323d7494c3ea280a9b0dfb73f953f5c1cb",
+        ),
+        (
+            "f2b539a401e4e8402869d5e1502dbc3156dbce93583f516a4947b333260d5af1a34810c6a00200000003525363ffffffff01d305e2000000000005acab535200a265fe77",
+            "",
+            0,
+            -1435650456,
+            "41617b27321a830c712638dbb156dae23d4ef181c7a06728ccbf3153ec53d7dd",
+        ),
+        (
+            "e8c0dec5026575ddf31343c20aeeca8770afb33d4e562aa8ee52eeda6b88806fdfd4fe0a97030000000953acabab65ab516552ffffffffdde122c2c3e9708874286465f8105f43019e837746686f442666629088a970e0010000000153ffffffff01f98eee0100000000025251fe87379a",
+            "63",
+            1,
+            633826334,
+            "abe441209165d25bc6d8368f2e7e7dc21019056719fef1ace45542aa2ef282e2",
+        ),
+        (
+            "e3cdbfb4014d90ae6a4401e85f7ac717adc2c035858bf6ff48979dd399d155bce1f150daea0300000002ac51a67a0d39017f6c71040000000005535200535200000000",
+            "",
+            0,
+            -1899950911,
+            "c1c7df8206e661d593f6455db1d61a364a249407f88e99ecad05346e495b38d7",
+        ),
+        (
+            "97bddc63015f1767619d56598ad0eb5c7e9f880b24a928fea1e040e95429c930c1dc653bdb0100000008ac53acac00005152aaa94eb90235ed10040000000000287bdd0400000000016a8077673a",
+            "acac6a536352655252",
+            0,
+            -813649781,
+            "5990b139451847343c9bb89cdba0e6daee6850b60e5b7ea505b04efba15f5d92",
+        ),
+        (
+            "97be4f7702dc20b087a1fdd533c7de762a3f2867a8f439bddf0dcec9a374dfd0276f9c55cc0300000000cdfb1dbe6582499569127bda6ca4aaff02c132dc73e15dcd91d73da77e92a32a13d1a0ba0200000002ab51ffffffff048cfbe202000000000900516351515363ac535128ce0100000000076aac5365ab6aabc84e8302000000000863536a53ab6a6552f051230500000000066aac535153510848d813",
+            "ac51",
+            0,
+            229541474,
+            "e5da9a416ea883be1f8b8b2d178463633f19de3fa82ae25d44ffb531e35bdbc8",
+        ),
+        (
+            "32fa0b0804e6ea101e137665a041cc2350b794e59bf42d9b09088b01cde806ec1bbea077df0200000008515153650000006506a11c55904258fa418e57b88b12724b81153260d3f4c9f080439789a391ab147aabb0fa0000000007000052ac51ab510986f2a15c0d5e05d20dc876dd2dafa435276d53da7b47c393f20900e55f163b97ce0b800000000008ab526a520065636a8087df7d4d9c985fb42308fb09dce704650719140aa6050e8955fa5d2ea46b464a333f870000000009636300636a6565006affffffff01994a0d040000000002536500000000",
+            "516563530065",
+            2,
+            -163068286,
+            "f58637277d2bc42e18358dc55f7e87e7043f5e33f4ce1fc974e715ef0d3d1c2a",
+        ),
+        (
+            "b3cad3a7041c2c17d90a2cd994f6c37307753fa3635e9ef05ab8b1ff121ca11239a0902e700300000009ab635300006aac5163ffffffffcec91722c7468156dce4664f3c783afef147f0e6f80739c83b5f09d5a09a57040200000004516a6552ffffffff969d1c6daf8ef53a70b7cdf1b4102fb3240055a8eaeaed2489617cd84cfd56cf020000000352ab53ffffffff46598b6579494a77b593681c33422a99559b9993d77ca2fa97833508b0c169f80200000009655300655365516351ffffffff04d7ddf800000000000853536a65ac6351ab09f3420300000000056aab65abac33589d04000000000952656a65655151acac944d6f0400000000006a8004ba",
+            "005165",
+            1,
+            1035865506,
+            "fe1dc9e8554deecf8f50c417c670b839cc9d650722ebaaf36572418756075d58",
+        ),
+        (
+            "86bc233e02ba3c647e356558e7252481a7769491fb46e883dd547a4ce9898fc9a1ca1b77790000000006ab5351abab51f0c1d09c37696d5c7c257788f5dff5583f4700687bcb7d4acfb48521dc953659e325fa390300000003acac5280f29523027225af03000000000963abac0065ab65acab7e59d90400000000016549dac846",
+            "53006aac52acac",
+            0,
+            711159875,
+            "880330ccde00991503ea598a6dfd81135c6cda9d317820352781417f89134d85",
+        ),
+        (
+            "ceecfa6c02b7e3345445b82226b15b7a097563fa7d15f3b0c979232b138124b62c0be007890200000009abac51536a63525253ffffffffbae481ccb4f15d94db5ec0d8854c24c1cc8642bd0c6300ede98a91ca13a4539a0200000001ac50b0813d023110f5020000000006acabac526563e2b0d0040000000009656aac0063516a536300000000",
+            "0063526500",
+            0,
+            -1862053821,
+            "e1600e6df8a6160a79ac32aa40bb4644daa88b5f76c0d7d13bf003327223f70c",
+        ),
+        (
+            "d3b7421e011f4de0f1cea9ba7458bf3486bee722519efab711a963fa8c100970cf7488b7bb0200000003525352dcd61b300148be5d05000000000000000000",
+            "535251536aac536a",
+            0,
+            -1960128125,
+            "29aa6d2d752d3310eba20442770ad345b7f6a35f96161ede5f07b33e92053e2a",
+        ),
+        (
+            "92c9fe210201e781b72554a0ed5e22507fb02434ddbaa69aff6e74ea8bad656071f1923f3f02000000056a63ac6a514470cef985ba83dcb8eee2044807bedbf0d983ae21286421506ae276142359c8c6a34d68020000000863ac63525265006aa796dd0102ca3f9d05000000000800abab52ab535353cd5c83010000000007ac00525252005322ac75ee",
+            "5165",
+            0,
+            97879971,
+            "6e6307cef4f3a9b386f751a6f40acebab12a0e7e17171d2989293cbec7fd45c2",
+        ),
+    ];
+
+    // Version 3 special transactions with a non-zero nType and an extraPayload, which Bitcoin's
+    // vectors can't have. Hashes were computed with a standalone implementation of Dash Core's
+    // SignatureHash which reproduces SIGHASH_VECTORS, the payload being signed after nLockTime
+    const SPECIAL_SIGHASH_VECTORS: [(&str, &str, usize, i32, &str); 6] = [
+        (
+            "0300010002a8b9f73d2debf4d7f931e17a47476dfe1af7f09d2b6a768557ccc4b050820e8c0200000002abacffffffff025ec0c15d1e18864916466f6726be992de110b709b81f36a4fdc1a50979c5750100000000ffffffff01b8de3e0500000000076a536563ac51ac2f2a076f3c49f81efa9d002f965b2d20c76a3a20e660977a666eda3278e048c31df6dce5a6cb8bd17cffec145b505f14cd52f58bebf7b01d924d4680489162c7d5",
+            "abab526353",
+            0,
+            1,
+            "eb293b8a6438dc4ccfa753693100ee6505140bd20a025cef68023435d1e29a4c",
+        ),
+        (
+            "03000500011073b5bb777cd3ab37c9fe4a5892bda43e3d7726cc6e9d68a76876fec29b1f07020000000365656598d7f9d003b1d11f0500000000026551030b900200000000056a5165ab6abaec280000000000060063abac656a742d85a7464f550f2b67b3cb0ed6a187e34e172f63d2de3f8d3dba28cff22fa3315e4d09731eece67d3e86bdaccd077e6291170912daab49c643eee55fa5689e58d0491c586ca69fa080d9",
+            "655263ab",
+            0,
+            130,
+            "7f6bc4d82ed683ae4b2f96ede9f39e3c04d23b2c831a5f3846ce121d21d7ecd4",
+        ),
+        (
+            "0300060002fed1184f1a54b38f2075cfefba978f7d3960614369f22f1a45e8ceafa01782ea00000000009d57af7f7dcbc720f1ba1f5ecf33322c368821f8efaa6b5a92087ce929184150175720ed0100000003656a63ffffffff03d64d490500000000056a6aab53abe8fe0a00000000000252ac5aab6e0100000000076565ab535351ab536a0d2e2809fe19287df1fb6f2982b62644a37aee125ffc9d1c0ae339014711ff231c2ef8b88eeacfe02a44ad",
+            "52",
+            1,
+            3,
+            "396daa9678e12ce5e57dc9057b19d5dabe62974c82aa47d1375a2739c146439a",
+        ),
+        (
+            "0300080003a0393a91d64f40d297a3c12fe31059540f945c8766e8d7b128008e341d342456010000000400ab6a00fffffffff9876d84af2a43b043a7d1128ed54780926790545eeb50700d51e4ac8aab84ca0200000000b7629eab16ea6be55b3bc40d2104b6deebf130f66a35050f9ab77d7ab5621db2b40bf6f4000000000265aca6a63035022c8aba0400000000026352fe7fbc040000000001003d85a73dfd0401c30f4315824f3bcf4c813b852d2e1f718817319111e3879e71ac840a29e631d529792cc11a41d9b8aafd15c241bcbee4b7cc1dfbd0ef5adbc49186a1df3c18e9a8326bc36a5ee489d15f69b291ba186361b9ae54ee8510927d8145c0731d7a1de5d38e09df29c04f3afb4cddc82b33496c1905b0169b40e056a124af88567534f2d4fa5670fd4cab82194193fd88100b31464feb72811594b713f5f79ca5b726e6fcc21f826d8ea8400cc50453a9e71652f30ee0f37b8b927b616e0363d71a942503a279d78146bc137cfcd9ca3d9820126deb7a518589b55855877bbeed9c9626d22a94a66d2f1fdebe48887d3a9ff924aef9a67b06a9955e5d5d3a0c1f22d9562fa520",
+            "63",
+            0,
+            167906833,
+            "73c1b61b8a2b9f09c500d5cf6fc230bb7432550dd6e7529f881e602600928937",
+        ),
+        (
+            "030009000303314785991520b2d19fec003c91401023bb6c54a68d8b2e36b173ac246dbb5503000000026a53ffffffff339b5a54b73967a5cc2abc50fb3ea3db98e227e48602c10c04e68c4330a68fc00200000002636affffffffeca6299db7be81a759ee54291ccb53292905c6ed9a1426d41749ecaf039fc25c0000000003516365ffffffff0246e5180500000000046a5163abe98260020000000005516a6553abed9936912199ab82381311b9a008a3e8c465d0be7947e092f98287a565fa684d23964931c12c",
+            "02ab51ab52",
+            1,
+            129,
+            "98135d792115a18b1d9c6afc189daa9e71e0b6ea199446884bebaca7601f2643",
+        ),
+        (
+            "0300020003a195e2dbac3829d70e66c750dc77e018cb6a047d8594b2ebbb55733875deca890000000004ac65ab6affffffff223b5f0e8d37f1162db7f591b5f185fccaa5d4c9270f3d2ea1f31c924876736602000000026363ffffffffd2a4bd8a1f0c566c995de35644b3a1162bfca3a7d47f6eef8098f17b587abaac010000000265abffffffff0164bba604000000000700ab5151655151266b42ce0c751a1f1ba7df318cb785b662",
+            "00",
+            2,
+            369746265,
+            "f9d34e07610c561ed52a962dabf27b4be3a8f50343832150980a9e2594364a2b",
+        ),
+    ];
+
+    fn transaction() -> Transaction {
+        let mut tx = fixtures::coinbase_transaction(1).base;
+        tx.inputs = (1..=2u8)
+            .map(|i| TransactionInput {
+                input_hash: UInt256([i; 32]),
+                index: i as u32,
+                script: Some(vec![0x76, 0xa9, 0x14, i, 0x88, 0xac]),
+                signature: Some(vec![0x01, i]),
+                sequence: u32::MAX - i as u32,
+                amount: None,
+            })
+            .collect();
+        tx.outputs = (1..=2u64)
+            .map(|i| TransactionOutput {
+                amount: i * 1000,
+                script: Some(vec![0x6a, i as u8]),
+                address: None,
+            })
+            .collect();
+        tx.extra_payload = Some(vec![1, 2, 3]);
+        tx
+    }
+
+    #[test]
+    fn sighash_all_matches_subscript_serialization() {
+        let tx = transaction();
+        for i in 0..tx.inputs.len() {
+            let script = tx.inputs[i].script.clone().unwrap();
+            let data = tx.to_data_with_subscript_index(i as u64);
+            assert_eq!(
+                tx.signature_hash(i, &script, SIGHASH_ALL),
+                UInt256(sha256d::Hash::hash(&data).into_inner())
+            );
+        }
+    }
+
+    #[test]
+    fn sighash_types_commit_to_expected_parts() {
+        let tx = transaction();
+        let script = tx.inputs[0].script.clone().unwrap();
+        let hash =
+            |tx: &Transaction, sighash_type: u32| tx.signature_hash(0, &script, sighash_type);
+        let mut one = [0u8; 32];
+        one[0] = 1;
+        assert_eq!(tx.signature_hash(2, &script, SIGHASH_ALL), UInt256(one));
+        let mut single_input = tx.clone();
+        single_input.inputs.truncate(1);
+        single_input.outputs.clear();
+        assert_eq!(hash(&single_input, SIGHASH_SINGLE), UInt256(one));
+        // the extraPayload is signed
+        let mut other_payload = tx.clone();
+        other_payload.extra_payload = Some(vec![1, 2, 4]);
+        assert_ne!(hash(&tx, SIGHASH_ALL), hash(&other_payload, SIGHASH_ALL));
+        assert_ne!(hash(&tx, SIGHASH_NONE), hash(&other_payload, SIGHASH_NONE));
+        // NONE leaves every output free, SINGLE only the other ones
+        let mut other_second_output = tx.clone();
+        other_second_output.outputs[1].amount += 1;
+        let mut other_first_output = tx.clone();
+        other_first_output.outputs[0].amount += 1;
+        assert_ne!(hash(&tx, SIGHASH_ALL), hash(&other_second_output, SIGHASH_ALL));
+        assert_eq!(hash(&tx, SIGHASH_NONE), hash(&other_first_output, SIGHASH_NONE));
+        assert_eq!(hash(&tx, SIGHASH_SINGLE), hash(&other_second_output, SIGHASH_SINGLE));
+        assert_ne!(hash(&tx, SIGHASH_SINGLE), hash(&other_first_output, SIGHASH_SINGLE));
+        // sequences of the other inputs are only signed with ALL
+        let mut other_sequence = tx.clone();
+        other_sequence.inputs[1].sequence = 0;
+        assert_ne!(hash(&tx, SIGHASH_ALL), hash(&other_sequence, SIGHASH_ALL));
+        assert_eq!(hash(&tx, SIGHASH_NONE), hash(&other_sequence, SIGHASH_NONE));
+        assert_eq!(hash(&tx, SIGHASH_SINGLE), hash(&other_sequence, SIGHASH_SINGLE));
+        // ANYONECANPAY leaves the other inputs free
+        let mut other_input = tx.clone();
+        other_input.inputs[1].input_hash = UInt256([9u8; 32]);
+        let all_anyone_can_pay = SIGHASH_ALL | SIGHASH_ANYONECANPAY;
+        assert_ne!(hash(&tx, SIGHASH_ALL), hash(&other_input, SIGHASH_ALL));
+        assert_eq!(hash(&tx, all_anyone_can_pay), hash(&other_input, all_anyone_can_pay));
+        assert_ne!(hash(&tx, SIGHASH_ALL), hash(&tx, all_anyone_can_pay));
+    }
+
+    #[test]
+    fn decoded_transaction_can_be_signed_with_attached_prevouts() {
+        let tx = transaction();
+        let data = tx.to_data();
+        let mut decoded = Transaction::decode(&data, &mut 0).expect("tx should be parsed");
+        assert_eq!(decoded.input_signature_hash(0, SIGHASH_ALL), None);
+        assert_eq!(decoded.fee(), None);
+        for (i, input) in tx.inputs.iter().enumerate() {
+            let prevout = TransactionOutput {
+                amount: 2000,
+                script: input.script.clone(),
+                address: None,
+            };
+            assert!(decoded.attach_prevout(i, &prevout));
+        }
+        assert!(!decoded.attach_prevout(2, &tx.outputs[0]));
+        assert_eq!(decoded.to_data(), data);
+        assert_eq!(decoded.fee(), Some(1000));
+        for (i, input) in tx.inputs.iter().enumerate() {
+            let script = input.script.as_ref().unwrap();
+            assert_eq!(
+                decoded.input_signature_hash(i, SIGHASH_SINGLE),
+                Some(tx.signature_hash(i, script, SIGHASH_SINGLE))
+            );
+            assert_eq!(
+                decoded.to_data_with_subscript_index(i as u64),
+                tx.to_data_with_subscript_index(i as u64)
+            );
+        }
+    }
+
+    #[test]
+    fn code_separators_are_not_signed() {
+        let tx = transaction();
+        let script = vec![0xab, 0x76, 0xa9, 0x02, 0xab, 0xab, 0x88, 0xab, 0xac];
+        let stripped = vec![0x76, 0xa9, 0x02, 0xab, 0xab, 0x88, 0xac];
+        assert_eq!(
+            tx.signature_hash(1, &script, SIGHASH_ALL),
+            tx.signature_hash(1, &stripped, SIGHASH_ALL)
+        );
+    }
+
+    #[test]
+    fn sighash_vectors() {
+        for (raw_tx, script_code, input_index, hash_type, expected) in SIGHASH_VECTORS {
+            let data = Vec::<u8>::from_hex(raw_tx).unwrap();
+            let tx = Transaction::decode(&data, &mut 0).expect("tx should be parsed");
+            assert!(tx.extra_payload.is_none());
+            // whatever their nType is, transactions are serialized back as received
+            assert_eq!(tx.to_data(), data);
+            let script_code = Vec::<u8>::from_hex(script_code).unwrap();
+            assert_eq!(
+                tx.signature_hash(input_index, &script_code, hash_type as u32),
+                UInt256::from_hex(expected).unwrap().reversed(),
+                "{}",
+                raw_tx
+            );
+        }
+        for (raw_tx, script_code, input_index, hash_type, expected) in SPECIAL_SIGHASH_VECTORS {
+            let data = Vec::<u8>::from_hex(raw_tx).unwrap();
+            let mut tx = Transaction::decode(&data, &mut 0).expect("tx should be parsed");
+            assert_eq!(tx.version, 3);
+            assert!(Transaction::is_special(tx.version, tx.tx_type));
+            assert!(tx.extra_payload.is_some());
+            assert_eq!(tx.to_data(), data);
+            let script_code = Vec::<u8>::from_hex(script_code).unwrap();
+            let hash = tx.signature_hash(input_index, &script_code, hash_type as u32);
+            assert_eq!(hash, UInt256::from_hex(expected).unwrap().reversed(), "{}", raw_tx);
+            // the payload is part of what is signed
+            tx.extra_payload.as_mut().unwrap()[0] ^= 1;
+            assert_ne!(tx.signature_hash(input_index, &script_code, hash_type as u32), hash);
+        }
+        let data = Vec::<u8>::from_hex(SIGHASH_VECTORS[0].0).unwrap();
+        let tx = Transaction::decode(&data, &mut 0).unwrap();
+        assert_eq!(tx.tx_type, TransactionType::Unknown(0xab91));
+        assert!(!Transaction::is_special(tx.version, tx.tx_type));
+
+        // SIGHASH_SINGLE without an output at the input index signs 1, as in Dash Core
+        let data = Vec::<u8>::from_hex(SIGHASH_VECTORS[2].0).unwrap();
+        let tx = Transaction::decode(&data, &mut 0).unwrap();
+        assert_eq!((tx.inputs.len(), tx.outputs.len()), (2, 1));
+        let mut one = [0u8; 32];
+        one[0] = 1;
+        for hash_type in [SIGHASH_SINGLE, SIGHASH_SINGLE | SIGHASH_ANYONECANPAY] {
+            assert_eq!(tx.signature_hash(1, &[0x63], hash_type), UInt256(one));
+        }
     }
 }
@@ -0,0 +1,219 @@
+use crate::tx::transaction::TX_UNCONFIRMED;
+use crate::tx::{Transaction, TransactionInput, TransactionOutput, TransactionType};
+use dash_spv_primitives::crypto::UInt256;
+
+// Version of classic transactions
+pub const TX_VERSION: u16 = 2;
+// Minimum version of transactions carrying an extraPayload
+pub const SPECIAL_TX_VERSION: u16 = 3;
+// Length of a P2PKH scriptSig: push of a DER signature with sighash byte (up to 73 bytes,
+// 72 with low S) and push of a compressed public key
+pub const P2PKH_SIGNATURE_SCRIPT_SIZE: usize = 1 + 72 + 1 + 33;
+// Default relay fee rate of Dash Core, in duffs per kB
+pub const DEFAULT_FEE_PER_KB: u64 = 1000;
+
+/// Assembles an unsigned Transaction from inputs, outputs and an optional special payload
+#[derive(Clone, Debug)]
+pub struct TransactionBuilder {
+    version: u16,
+    tx_type: TransactionType,
+    inputs: Vec<TransactionInput>,
+    outputs: Vec<TransactionOutput>,
+    lock_time: u32,
+    extra_payload: Option<Vec<u8>>,
+}
+
+impl Default for TransactionBuilder {
+    fn default() -> Self {
+        Self {
+            version: TX_VERSION,
+            tx_type: TransactionType::Classic,
+            inputs: vec![],
+            outputs: vec![],
+            lock_time: 0,
+            extra_payload: None,
+        }
+    }
+}
+
+impl TransactionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn version(mut self, version: u16) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn lock_time(mut self, lock_time: u32) -> Self {
+        self.lock_time = lock_time;
+        self
+    }
+
+    /// Spends output index of transaction hash, whose amount and script are attached
+    pub fn add_input(mut self, hash: UInt256, index: u32, prevout: &TransactionOutput) -> Self {
+        let mut input = TransactionInput {
+            input_hash: hash,
+            index,
+            script: None,
+            signature: None,
+            sequence: u32::MAX,
+            amount: None,
+        };
+        input.attach_prevout(prevout);
+        self.inputs.push(input);
+        self
+    }
+
+    /// Adds an input as is, e.g. already signed or with a custom sequence
+    pub fn add_transaction_input(mut self, input: TransactionInput) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    pub fn add_output(mut self, amount: u64, script: Vec<u8>) -> Self {
+        self.outputs.push(TransactionOutput {
+            amount,
+            script: Some(script),
+            address: None,
+        });
+        self
+    }
+
+    /// Makes it a special transaction of tx_type carrying payload as extraPayload
+    pub fn special_payload(mut self, tx_type: TransactionType, payload: Vec<u8>) -> Self {
+        self.version = self.version.max(SPECIAL_TX_VERSION);
+        self.tx_type = tx_type;
+        self.extra_payload = Some(payload);
+        self
+    }
+
+    /// Serialization with the scriptSig of inputs that aren't signed yet left empty
+    pub fn to_data(&self) -> Vec<u8> {
+        Transaction::data_with_subscript_index_static(
+            u64::MAX,
+            self.version,
+            self.tx_type,
+            &self.inputs,
+            &self.outputs,
+            self.lock_time,
+            self.extra_payload.as_deref(),
+        )
+    }
+
+    /// Size of the signed transaction, counting a P2PKH scriptSig for every unsigned input
+    pub fn estimated_size(&self) -> usize {
+        let unsigned_inputs = self
+            .inputs
+            .iter()
+            .filter(|input| input.signature.is_none())
+            .count();
+        // the empty scriptSig length is 1 byte, as is the one of a P2PKH scriptSig
+        self.to_data().len() + unsigned_inputs * P2PKH_SIGNATURE_SCRIPT_SIZE
+    }
+
+    /// Fee of the signed transaction at fee_per_kb duffs per 1000 bytes, rounded up,
+    /// or None when it overflows
+    pub fn estimated_fee(&self, fee_per_kb: u64) -> Option<u64> {
+        Some((self.estimated_size() as u64).checked_mul(fee_per_kb)?.div_ceil(1000))
+    }
+
+    /// None when an input amount is unknown or the sum overflows, as in Transaction::fee
+    pub fn inputs_amount(&self) -> Option<u64> {
+        self.inputs
+            .iter()
+            .try_fold(0u64, |sum, input| sum.checked_add(input.amount?))
+    }
+
+    /// None when the sum overflows
+    pub fn outputs_amount(&self) -> Option<u64> {
+        self.outputs
+            .iter()
+            .try_fold(0u64, |sum, output| sum.checked_add(output.amount))
+    }
+
+    pub fn build(self) -> Transaction {
+        Transaction {
+            inputs: self.inputs,
+            outputs: self.outputs,
+            lock_time: self.lock_time,
+            version: self.version,
+            // changes with the signatures
+            tx_hash: None,
+            tx_type: self.tx_type,
+            extra_payload: self.extra_payload,
+            payload_offset: 0,
+            block_height: TX_UNCONFIRMED as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tx::script::ScriptType;
+    use crate::tx::transaction_builder::{TransactionBuilder, DEFAULT_FEE_PER_KB};
+    use crate::tx::{Transaction, TransactionOutput, TransactionType};
+    use dash_spv_primitives::crypto::{UInt160, UInt256};
+
+    fn p2pkh(seed: u8) -> Vec<u8> {
+        ScriptType::PayToPubkeyHash(UInt160([seed; 20])).script().unwrap()
+    }
+
+    #[test]
+    fn builder_estimates_signed_size_and_fee() {
+        let prevout = TransactionOutput {
+            amount: 100_000,
+            script: Some(p2pkh(1)),
+            address: None,
+        };
+        let builder = TransactionBuilder::new()
+            .add_input(UInt256([1u8; 32]), 0, &prevout)
+            .add_output(60_000, p2pkh(2))
+            .add_output(39_000, p2pkh(3));
+        // the usual 1 input 2 outputs P2PKH transaction is 226 bytes once signed
+        assert_eq!(builder.estimated_size(), 226);
+        assert_eq!(builder.estimated_fee(DEFAULT_FEE_PER_KB), Some(226));
+        assert_eq!(builder.estimated_fee(1500), Some(339));
+        assert_eq!(builder.estimated_fee(u64::MAX), None);
+        assert_eq!(builder.inputs_amount(), Some(100_000));
+        assert_eq!(builder.outputs_amount(), Some(99_000));
+        // an input without amount or amounts past u64::MAX give no total
+        let mut unknown = builder.clone();
+        unknown.inputs[0].amount = None;
+        assert_eq!(unknown.inputs_amount(), None);
+        let overflowing = builder.clone().add_output(u64::MAX, p2pkh(4));
+        assert_eq!(overflowing.outputs_amount(), None);
+        let data = builder.to_data();
+        let tx = builder.build();
+        assert_eq!(tx.to_data(), data);
+        assert_eq!(tx.fee(), Some(1000));
+        let decoded = Transaction::decode(&data, &mut 0).expect("tx should be parsed");
+        assert_eq!(decoded.to_data(), data);
+
+        // signing doesn't change the size of a P2PKH input
+        let mut signed = tx.clone();
+        signed.inputs[0].signature = Some(vec![0u8; 107]);
+        assert_eq!(signed.to_data().len(), 226);
+    }
+
+    #[test]
+    fn builder_attaches_special_payload() {
+        let prevout = TransactionOutput {
+            amount: 2000,
+            script: Some(p2pkh(1)),
+            address: None,
+        };
+        let builder = TransactionBuilder::new()
+            .add_input(UInt256([1u8; 32]), 0, &prevout)
+            .add_output(1000, p2pkh(2))
+            .special_payload(TransactionType::ProviderUpdateRevocation, vec![1, 2, 3]);
+        let data = builder.to_data();
+        assert_eq!(builder.estimated_size(), data.len() + 107);
+        let tx = Transaction::decode(&data, &mut 0).expect("tx should be parsed");
+        assert_eq!(tx.version, 3);
+        assert_eq!(tx.tx_type, TransactionType::ProviderUpdateRevocation);
+        assert_eq!(tx.extra_payload, Some(vec![1, 2, 3]));
+        assert_eq!(tx.to_data(), data);
+    }
+}
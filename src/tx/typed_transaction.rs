@@ -0,0 +1,227 @@
+use crate::error::{read_field, Error};
+use crate::tx::transaction::ITransaction;
+use crate::tx::{
+    CoinbaseTransaction, ProviderRegistrationTransaction, ProviderUpdateRegistrarTransaction,
+    ProviderUpdateRevocationTransaction, ProviderUpdateServiceTransaction,
+    QuorumCommitmentTransaction, Transaction, TransactionType,
+};
+use byte::ctx::Endian;
+use byte::TryRead;
+use dash_spv_primitives::crypto::UInt256;
+
+/// Transaction decoded according to its type
+#[derive(Debug, Clone)]
+pub enum TypedTransaction {
+    Classic(Transaction),
+    ProviderRegistration(ProviderRegistrationTransaction),
+    ProviderUpdateService(ProviderUpdateServiceTransaction),
+    ProviderUpdateRegistrar(ProviderUpdateRegistrarTransaction),
+    ProviderUpdateRevocation(ProviderUpdateRevocationTransaction),
+    Coinbase(CoinbaseTransaction),
+    QuorumCommitment(QuorumCommitmentTransaction),
+}
+
+impl<'a> TryRead<'a, Endian> for TypedTransaction {
+    fn try_read(bytes: &'a [u8], _endian: Endian) -> byte::Result<(Self, usize)> {
+        let offset = &mut 0;
+        let tx = Self::decode(bytes, offset)?;
+        Ok((tx, *offset))
+    }
+}
+
+impl TypedTransaction {
+    pub fn decode(bytes: &[u8], offset: &mut usize) -> Result<Self, Error> {
+        // peek at the header, the whole transaction is decoded from offset below
+        let mut header_offset = *offset;
+        let version = read_field::<u16>(bytes, &mut header_offset, "Transaction", "version")?;
+        let tx_type = TransactionType::from(read_field::<u16>(
+            bytes,
+            &mut header_offset,
+            "Transaction",
+            "tx_type",
+        )?);
+        if !Transaction::is_special(version, tx_type) {
+            return Ok(Self::Classic(Transaction::decode(bytes, offset)?));
+        }
+        Ok(match tx_type {
+            TransactionType::ProviderRegistration => Self::ProviderRegistration(
+                ProviderRegistrationTransaction::decode(bytes, offset)?,
+            ),
+            TransactionType::ProviderUpdateService => Self::ProviderUpdateService(
+                ProviderUpdateServiceTransaction::decode(bytes, offset)?,
+            ),
+            TransactionType::ProviderUpdateRegistrar => Self::ProviderUpdateRegistrar(
+                ProviderUpdateRegistrarTransaction::decode(bytes, offset)?,
+            ),
+            TransactionType::ProviderUpdateRevocation => Self::ProviderUpdateRevocation(
+                ProviderUpdateRevocationTransaction::decode(bytes, offset)?,
+            ),
+            TransactionType::Coinbase => {
+                Self::Coinbase(CoinbaseTransaction::decode(bytes, offset)?)
+            }
+            TransactionType::QuorumCommitment => {
+                Self::QuorumCommitment(QuorumCommitmentTransaction::decode(bytes, offset)?)
+            }
+            _ => {
+                return Err(Error::BadInput {
+                    structure: "Transaction",
+                    field: "tx_type",
+                    offset: *offset + 2,
+                    reason: "unsupported special transaction type",
+                })
+            }
+        })
+    }
+
+    pub fn base(&self) -> &Transaction {
+        match self {
+            Self::Classic(tx) => tx,
+            Self::ProviderRegistration(tx) => &tx.base,
+            Self::ProviderUpdateService(tx) => &tx.base,
+            Self::ProviderUpdateRegistrar(tx) => &tx.base,
+            Self::ProviderUpdateRevocation(tx) => &tx.base,
+            Self::Coinbase(tx) => &tx.base,
+            Self::QuorumCommitment(tx) => &tx.base,
+        }
+    }
+
+    pub fn tx_hash(&self) -> Option<UInt256> {
+        self.base().tx_hash
+    }
+
+    /// Special transaction part of the payload, None for classic transactions
+    pub fn special(&self) -> Option<&dyn ITransaction> {
+        match self {
+            Self::Classic(_) => None,
+            Self::ProviderRegistration(tx) => Some(tx),
+            Self::ProviderUpdateService(tx) => Some(tx),
+            Self::ProviderUpdateRegistrar(tx) => Some(tx),
+            Self::ProviderUpdateRevocation(tx) => Some(tx),
+            Self::Coinbase(tx) => Some(tx),
+            Self::QuorumCommitment(tx) => Some(tx),
+        }
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        match self {
+            Self::Classic(tx) => tx.to_data(),
+            Self::ProviderRegistration(tx) => tx.to_data(),
+            Self::ProviderUpdateService(tx) => tx.to_data(),
+            Self::ProviderUpdateRegistrar(tx) => tx.to_data(),
+            Self::ProviderUpdateRevocation(tx) => tx.to_data(),
+            Self::Coinbase(tx) => tx.to_data(),
+            Self::QuorumCommitment(tx) => tx.to_data(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{LLMQType, MasternodeType, SocketAddress};
+    use crate::fixtures;
+    use crate::tx::{
+        ITransaction, ProviderRegistrationTransaction, ProviderUpdateRevocationTransaction,
+        QuorumCommitmentTransaction, Transaction, TransactionType, TypedTransaction,
+    };
+    use dash_spv_primitives::crypto::{UInt128, UInt160, UInt256, UInt384, UInt768};
+    use dash_spv_primitives::hashes::{sha256d, Hash};
+
+    fn base(tx_type: TransactionType) -> Transaction {
+        let mut base = fixtures::coinbase_transaction(1).base;
+        base.tx_type = tx_type;
+        base.inputs[0].index = 0;
+        base
+    }
+
+    fn decode_again(data: &[u8]) -> TypedTransaction {
+        let offset = &mut 0;
+        let tx = TypedTransaction::decode(data, offset).expect("transaction should be parsed");
+        assert_eq!(*offset, data.len());
+        assert_eq!(tx.to_data(), data);
+        assert_eq!(
+            tx.tx_hash(),
+            Some(UInt256(sha256d::Hash::hash(data).into_inner()))
+        );
+        tx
+    }
+
+    #[test]
+    fn special_transactions_round_trip() {
+        let pro_reg_tx = ProviderRegistrationTransaction {
+            base: base(TransactionType::ProviderRegistration),
+            provider_registration_transaction_version: 2,
+            provider_type: MasternodeType::Evo,
+            provider_mode: 0,
+            collateral_hash: UInt256([1u8; 32]),
+            collateral_index: 1,
+            socket_address: SocketAddress {
+                ip_address: UInt128([2u8; 16]),
+                port: 9999,
+            },
+            owner_key_hash: UInt160([3u8; 20]),
+            operator_key: UInt384([4u8; 48]),
+            voting_key_hash: UInt160([5u8; 20]),
+            operator_reward: 100,
+            script_payout: vec![0x76, 0xa9],
+            inputs_hash: UInt256([6u8; 32]),
+            platform_node_id: UInt160([7u8; 20]),
+            platform_p2p_port: 26656,
+            platform_http_port: 443,
+            payload_signature: vec![8u8; 65],
+        };
+        let data = pro_reg_tx.to_data();
+        // any transaction keeps the raw payload and hashes the full serialization
+        let raw = Transaction::decode(&data, &mut 0).unwrap();
+        assert_eq!(raw.extra_payload, Some(pro_reg_tx.payload_data()));
+        assert_eq!(raw.to_data(), data);
+        let tx = decode_again(&data);
+        match &tx {
+            TypedTransaction::ProviderRegistration(tx) => assert_eq!(tx.platform_http_port, 443),
+            _ => panic!("ProRegTx expected"),
+        }
+        let special = tx.special().unwrap();
+        assert_eq!(special.transaction_type(), TransactionType::ProviderRegistration);
+        // the signature covers everything but itself
+        assert_eq!(special.payload_data().len(), special.payload_data_for().len() + 66);
+
+        let pro_up_rev_tx = ProviderUpdateRevocationTransaction {
+            base: base(TransactionType::ProviderUpdateRevocation),
+            provider_update_revocation_transaction_version: 1,
+            provider_registration_transaction_hash: UInt256([1u8; 32]),
+            reason: 1,
+            inputs_hash: UInt256([2u8; 32]),
+            payload_signature: UInt768([3u8; 96]),
+        };
+        decode_again(&pro_up_rev_tx.to_data());
+
+        let mut qc_base = base(TransactionType::QuorumCommitment);
+        qc_base.inputs.clear();
+        qc_base.outputs.clear();
+        let qc_tx = QuorumCommitmentTransaction {
+            base: qc_base,
+            quorum_commitment_transaction_version: 1,
+            quorum_commitment_height: 1000,
+            quorum_entry: fixtures::llmq_entry(LLMQType::Llmqtype50_60, 4),
+        };
+        decode_again(&qc_tx.to_data());
+    }
+
+    #[test]
+    fn payload_size_mismatch_is_rejected() {
+        let pro_up_rev_tx = ProviderUpdateRevocationTransaction {
+            base: base(TransactionType::ProviderUpdateRevocation),
+            provider_update_revocation_transaction_version: 1,
+            provider_registration_transaction_hash: UInt256([1u8; 32]),
+            reason: 1,
+            inputs_hash: UInt256([2u8; 32]),
+            payload_signature: UInt768([3u8; 96]),
+        };
+        let mut data = pro_up_rev_tx.to_data();
+        let payload_size_position = data.len() - 164 - 1;
+        assert_eq!(data[payload_size_position], 164);
+        data[payload_size_position] = 165;
+        data.push(0);
+        let err = ProviderUpdateRevocationTransaction::decode(&data, &mut 0).unwrap_err();
+        assert_eq!(err.field(), "extra_payload");
+    }
+}